@@ -1,17 +1,27 @@
 use std::fs::read_to_string;
 
 use crate::args::AstArgs;
+use crate::parser::Parser;
 use crate::pretty::ast_pretty_string;
 
 
 pub fn ast(args: AstArgs) {
     let buffer = read_to_string(args.path).expect("Error reading file");
 
-    // let ast = parse_code(&buffer);
+    let ast = match Parser::new(&buffer).parse() {
+        Ok(ast) => ast,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("{}", err);
+            }
 
-    // println!("ast has {} nodes", ast.len());
+            std::process::exit(1);
+        }
+    };
 
-    // for node in &ast {
-    //     println!("{}", ast_pretty_string(&node));
-    // }
+    println!("ast has {} nodes", ast.len());
+
+    for node in &ast {
+        println!("{}", ast_pretty_string(node));
+    }
 }
\ No newline at end of file
@@ -1,10 +1,14 @@
+use std::collections::HashMap;
 use std::fs::read_to_string;
 use std::path::Path;
 
 use crate::args::RunArgs;
 use crate::compiler::Compiler;
+use crate::component::builtin_component;
+use crate::component::Object;
 use crate::parser::Parser;
 use crate::pretty::bytecode_to_str;
+use crate::typecheck::TypeChecker;
 use crate::types::Action;
 use crate::vm::Vm;
 
@@ -22,7 +26,16 @@ pub fn run(args: RunArgs, log: usize) {
         false => args.path.to_string(),
     };
 
-    let ast = Parser::new(&code).parse();
+    let ast = match Parser::new(&code).parse() {
+        Ok(ast) => ast,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("{}", err);
+            }
+
+            std::process::exit(1);
+        }
+    };
 
     if log >= 1 {
         for node in &ast {
@@ -30,24 +43,56 @@ pub fn run(args: RunArgs, log: usize) {
         }
     }
 
-    let res = Compiler::new().compile(ast);
+    if let Err(errors) = TypeChecker::new().check(&ast) {
+        for err in &errors {
+            eprintln!("{}", err);
+        }
+
+        std::process::exit(1);
+    }
+
+    let res = match Compiler::new().compile(ast) {
+        Ok(res) => res,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("{}", err);
+            }
+
+            std::process::exit(1);
+        }
+    };
 
     println!("consts: {:?}", res.consts);
     println!("bytecode: {}", bytecode_to_str(&res.bytecode));
 
-    // for c in res.consts {
-    //     vm.store_const(c);
-    // }
+    for (i, f) in res.functions.iter().enumerate() {
+        println!("block {}: {}", i + 1, bytecode_to_str(f));
+    }
+
+    vm.load(&res);
 
-    vm.create_code_block(&res.bytecode);
+    // Fields are stored by the ids of their names
+    let names: HashMap<usize, &str> = res.idents.iter().map(|(name, id)| (*id, name.as_str())).collect();
+    let mut components: HashMap<usize, Box<dyn Object>> = HashMap::new();
 
     loop {
-        let actions = vm.work();
+        let actions = match vm.work() {
+            Ok(actions) => actions,
+            Err(err) => {
+                eprintln!("{}", err);
+
+                std::process::exit(1);
+            }
+        };
 
         for action in actions {
             match action {
-                Action::Construct{ id } => {
-                    log::info!("Construct {}", id);
+                Action::Construct{ id, name } => {
+                    log::info!("Construct {} {}", id, name);
+
+                    if let Some(component) = builtin_component(name) {
+                        components.insert(*id, component);
+                    }
                 }
                 Action::Destruct{ id } => {
                     log::info!("Destruct {}", id);
@@ -57,6 +102,10 @@ pub fn run(args: RunArgs, log: usize) {
                 }
                 Action::StoreField{ id, field, val } => {
                     log::info!("StoreField {} {} {:?}", id, field, val);
+
+                    if let (Some(component), Some(name)) = (components.get_mut(id), names.get(field)) {
+                        component.set_property(name, val);
+                    }
                 }
                 Action::Call{ id, args } => {
                     log::info!("Call {} {:?}", id, args);
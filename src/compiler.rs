@@ -1,34 +1,109 @@
 use std::collections::HashMap;
+use std::fmt;
 
+use logos::Span;
+
+use crate::component::builtin_enums;
 use crate::parser::ASTNode;
+use crate::parser::BinOp;
+use crate::parser::EnumDef;
+use crate::parser::ForLoop;
+use crate::parser::Match;
+use crate::parser::NodeKind;
 use crate::parser::Op;
+use crate::parser::Pattern;
+use crate::parser::ProbAccess;
+use crate::parser::UnaryOp;
 use crate::types::Const;
 use crate::types::Value;
 use crate::vm::ByteCode;
 
+// Everything the vm needs to run a compiled program
 #[derive(Debug, Clone)]
 pub struct CompileRes {
     pub bytecode: Vec<ByteCode>,
+    // Function bodies, `bytecode` is code block 0 and `functions[i]` is
+    // code block i + 1
+    pub functions: Vec<Vec<ByteCode>>,
     pub consts: Vec<Const>,
+    pub idents: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error at {}..{}: {}", self.span.start, self.span.end, self.message)
+    }
+}
+
+// A loop being compiled. Jumps out of it are patched once its end is
+// known, `scopes` and `values` tell what a jump out has to clean up.
+#[derive(Debug, Clone)]
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>,
+    scopes: usize,
+    values: usize,
+}
+
+// A function body being compiled
+#[derive(Debug, Clone)]
+struct FunScope {
+    // Index of its first compile time scope
+    start: usize,
+    // Variables of enclosing functions it uses
+    captures: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Compiler {
-    pub consts: Vec<Value>,
-    pub idents: HashMap<String, usize>,
-    pub bytecode: Vec<ByteCode>,
+    consts: Vec<Value>,
+    idents: HashMap<String, usize>,
+    bytecode: Vec<ByteCode>,
+    functions: Vec<Vec<ByteCode>>,
+    // Function bodies being compiled, innermost last
+    funs: Vec<FunScope>,
+    loops: Vec<Loop>,
+    // Variables declared in each open scope and whether they are constant
+    scopes: Vec<HashMap<String, bool>>,
+    // Variants of the known enums and the number of values they carry
+    enums: HashMap<String, Vec<(String, usize)>>,
+    errors: Vec<CompileError>,
 }
 
 impl Compiler {
     pub fn new() -> Compiler {
+        let enums = builtin_enums()
+            .into_iter()
+            .map(|(name, variants)| {
+                (name.to_string(), variants.iter().map(|v| (v.to_string(), 0)).collect())
+            })
+            .collect();
+
         Compiler{
             consts: Vec::new(),
             idents: HashMap::new(),
             bytecode: Vec::new(),
+            functions: Vec::new(),
+            funs: Vec::new(),
+            loops: Vec::new(),
+            scopes: vec![HashMap::new()],
+            enums,
+            errors: Vec::new(),
         }
     }
 
+    // Equal constants share one slot in the pool
     fn store_const(&mut self, v: Value) -> usize {
+        if let Some(id) = self.consts.iter().position(|c| same_const(c, &v)) {
+            return id;
+        }
+
         let id = self.consts.len();
 
         self.consts.push(v);
@@ -49,65 +124,614 @@ impl Compiler {
         }
     }
 
+    fn load_none(&mut self) {
+        let id = self.store_const(Value::None);
+        self.bytecode.push(ByteCode::LoadConst(id));
+    }
+
+    fn emit_jump(&mut self, jump: ByteCode) -> usize {
+        self.bytecode.push(jump);
+        self.bytecode.len() - 1
+    }
+
+    // Points the jump at `at` to the next instruction
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.bytecode.len();
+
+        match &mut self.bytecode[at] {
+            ByteCode::Jump(t) | ByteCode::JumpIfFalse(t) | ByteCode::IterNext(t) => *t = target,
+            bc => unreachable!("{:?} is not a jump", bc),
+        }
+    }
+
+    fn error(&mut self, node: &ASTNode, message: String) {
+        self.errors.push(CompileError {
+            span: node.span.clone(),
+            message,
+        });
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+        self.bytecode.push(ByteCode::PushScope);
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+        self.bytecode.push(ByteCode::PopScope);
+    }
+
+    fn declare(&mut self, name: &str, constant: bool) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), constant);
+        }
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains_key(name))
+    }
+
+    fn is_constant(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    // A function using a variable of an enclosing function captures it,
+    // as does every function in between so it can pass the variable on.
+    // Globals are visible everywhere and never captured.
+    fn capture(&mut self, name: &str) {
+        let at = match self.scopes.iter().rposition(|scope| scope.contains_key(name)) {
+            Some(at) if at > 0 => at,
+            _ => return,
+        };
+
+        for fun in self.funs.iter_mut().filter(|fun| fun.start > at) {
+            if !fun.captures.iter().any(|c| c == name) {
+                fun.captures.push(name.to_string());
+            }
+        }
+    }
+
+    // Closes the scopes opened since `scopes` without changing the scope
+    // depth, used before jumping out of them
+    fn close_scopes(&mut self, scopes: usize) {
+        for _ in scopes..self.scopes.len() {
+            self.bytecode.push(ByteCode::PopScope);
+        }
+    }
+
+    // Blocks get a scope of their own only when they declare variables
+    fn compile_scoped_block(&mut self, nodes: &[ASTNode]) {
+        let scoped = declares(nodes);
+
+        if scoped {
+            self.push_scope();
+        }
+
+        self.compile_block(nodes);
+
+        if scoped {
+            self.pop_scope();
+        }
+    }
+
+    // Compiles the items of a block so that it leaves exactly one value
+    // on the stack, the value of the last item or none.
+    fn compile_block(&mut self, nodes: &[ASTNode]) {
+        for (i, node) in nodes.iter().enumerate() {
+            self.compile_node(node);
+
+            match (is_expr(node), i + 1 == nodes.len()) {
+                (true, false) => self.bytecode.push(ByteCode::Pop),
+                (false, true) => self.load_none(),
+                _ => {}
+            }
+        }
+
+        if nodes.is_empty() {
+            self.load_none();
+        }
+    }
+
+    // Adds the values of an array item to the array being built, `depth`
+    // is the number of loop values on the stack above the array. Every
+    // expression in the taken branch of a conditional and in each round
+    // of a loop becomes an item.
+    fn compile_array_item(&mut self, item: &ASTNode, depth: usize) {
+        match &item.kind {
+            NodeKind::If(if_expr) => {
+                self.compile_node(&if_expr.cond);
+                let jump_else = self.emit_jump(ByteCode::JumpIfFalse(0));
+
+                self.compile_array_items(&if_expr.body, depth);
+
+                let jump_end = self.emit_jump(ByteCode::Jump(0));
+                self.patch_jump(jump_else);
+
+                if let Some(else_body) = &if_expr.else_body {
+                    self.compile_array_items(else_body, depth);
+                }
+
+                self.patch_jump(jump_end);
+            },
+            NodeKind::ForLoop(for_loop) => {
+                let (start, jump_end) = self.compile_loop_start(for_loop);
+
+                for item in &for_loop.body {
+                    self.compile_array_item(item, depth + 2);
+                }
+
+                self.compile_loop_end(start, jump_end);
+            },
+            _ if is_expr(item) => {
+                self.compile_node(item);
+                self.bytecode.push(ByteCode::ArrayPush(depth));
+            },
+            _ => self.compile_node(item),
+        }
+    }
+
+    fn compile_array_items(&mut self, items: &[ASTNode], depth: usize) {
+        let scoped = declares(items);
+
+        if scoped {
+            self.push_scope();
+        }
+
+        for item in items {
+            self.compile_array_item(item, depth);
+        }
+
+        if scoped {
+            self.pop_scope();
+        }
+    }
+
+    // Arms are tried in order on a copy of the matched value, the value
+    // itself stays on the stack until an arm matches. No match gives none.
+    fn compile_match(&mut self, m: &Match) {
+        self.compile_node(&m.value);
+
+        let mut ends = Vec::new();
+
+        for arm in &m.arms {
+            let scoped = binds(&arm.pattern);
+
+            if scoped {
+                self.push_scope();
+            }
+
+            let mut fails = Vec::new();
+
+            self.bytecode.push(ByteCode::Dup);
+            self.compile_pattern(&arm.pattern, 0, &mut fails);
+
+            self.bytecode.push(ByteCode::Pop);
+            self.compile_block(&arm.body);
+
+            if scoped {
+                self.pop_scope();
+            }
+
+            ends.push(self.emit_jump(ByteCode::Jump(0)));
+
+            // Failed tests jump here leaving `depth` values to drop
+            let max_depth = fails.iter().map(|(_, depth)| *depth).max().unwrap_or(0);
+
+            for depth in (0..=max_depth).rev() {
+                for (jump, _) in fails.iter().filter(|(_, d)| *d == depth) {
+                    self.patch_jump(*jump);
+                }
+
+                if depth > 0 {
+                    self.bytecode.push(ByteCode::Pop);
+                }
+            }
+
+            if scoped {
+                self.bytecode.push(ByteCode::PopScope);
+            }
+        }
+
+        self.bytecode.push(ByteCode::Pop);
+        self.load_none();
+
+        for jump in ends {
+            self.patch_jump(jump);
+        }
+    }
+
+    // Tests the value on top of the stack against the pattern and consumes
+    // it. `depth` is the number of values between the tested value and the
+    // matched value, jumps taken on a mismatch are recorded with the
+    // number of values left on the stack.
+    fn compile_pattern(&mut self, pattern: &Pattern, depth: usize, fails: &mut Vec<(usize, usize)>) {
+        match pattern {
+            Pattern::Wildcard => self.bytecode.push(ByteCode::Pop),
+            Pattern::Bind(name) => {
+                self.declare(name, false);
+
+                let id = self.store_ident(name);
+                self.bytecode.push(ByteCode::Define(id));
+            },
+            Pattern::Lit(lit) => {
+                let id = self.store_const(lit.clone());
+                self.bytecode.push(ByteCode::LoadConst(id));
+                self.bytecode.push(ByteCode::Eq);
+
+                fails.push((self.emit_jump(ByteCode::JumpIfFalse(0)), depth));
+            },
+            Pattern::Type(name, inner) => {
+                self.compile_type_test(name, depth, fails);
+
+                match inner {
+                    Some(inner) => self.compile_pattern(inner, depth, fails),
+                    None => self.bytecode.push(ByteCode::Pop),
+                }
+            },
+            Pattern::Struct(name, fields) => {
+                self.compile_type_test(name, depth, fails);
+
+                for (field, pattern) in fields {
+                    let id = self.store_ident(field);
+                    self.bytecode.push(ByteCode::Dup);
+                    self.bytecode.push(ByteCode::LoadField(id));
+
+                    self.compile_pattern(pattern, depth + 1, fails);
+                }
+
+                self.bytecode.push(ByteCode::Pop);
+            },
+            Pattern::Variant(ty, variant, payload) => {
+                self.compile_type_test(ty, depth, fails);
+
+                let id = self.store_const(Value::Str(variant.clone()));
+                self.bytecode.push(ByteCode::Dup);
+                self.bytecode.push(ByteCode::VariantOf);
+                self.bytecode.push(ByteCode::LoadConst(id));
+                self.bytecode.push(ByteCode::Eq);
+
+                fails.push((self.emit_jump(ByteCode::JumpIfFalse(0)), depth + 1));
+
+                for (i, pattern) in payload.iter().enumerate() {
+                    self.bytecode.push(ByteCode::Dup);
+                    self.bytecode.push(ByteCode::LoadPayload(i));
+
+                    self.compile_pattern(pattern, depth + 1, fails);
+                }
+
+                self.bytecode.push(ByteCode::Pop);
+            },
+        }
+    }
+
+    // Checks the type of the value on top of the stack and keeps the value
+    fn compile_type_test(&mut self, name: &str, depth: usize, fails: &mut Vec<(usize, usize)>) {
+        let id = self.store_const(Value::Str(name.to_string()));
+
+        self.bytecode.push(ByteCode::Dup);
+        self.bytecode.push(ByteCode::TypeOf);
+        self.bytecode.push(ByteCode::LoadConst(id));
+        self.bytecode.push(ByteCode::Eq);
+
+        fails.push((self.emit_jump(ByteCode::JumpIfFalse(0)), depth + 1));
+    }
+
+    fn register_enum(&mut self, def: &EnumDef) {
+        let variants = def.variants
+            .iter()
+            .map(|v| (v.name.clone(), v.payload.len()))
+            .collect();
+
+        self.enums.insert(def.name.clone(), variants);
+    }
+
+    // Compiles `FlexDir.Row` and `Shape.Circle(1)`. Returns false when the
+    // access is not to an enum, variables shadow enums of the same name.
+    fn compile_variant(&mut self, node: &ASTNode, prob: &ProbAccess, args: &[ASTNode]) -> bool {
+        let ty = match &prob.object.kind {
+            NodeKind::Ident(name) if !self.is_declared(name) && self.enums.contains_key(name) => name.clone(),
+            _ => return false,
+        };
+
+        let arity = self.enums[&ty]
+            .iter()
+            .find(|(variant, _)| *variant == prob.property)
+            .map(|(_, arity)| *arity);
+
+        match arity {
+            None => {
+                self.error(node, format!("enum '{}' has no variant '{}'", ty, prob.property));
+            },
+            Some(arity) if arity != args.len() => {
+                self.error(node, format!(
+                    "variant '{}.{}' takes {} values but {} were given",
+                    ty, prob.property, arity, args.len()
+                ));
+            },
+            Some(arity) => {
+                for arg in args {
+                    self.compile_node(arg);
+                }
+
+                let id = self.store_const(Value::Enum {
+                    ty,
+                    variant: prob.property.clone(),
+                    payload: Vec::new(),
+                });
+
+                match arity {
+                    0 => self.bytecode.push(ByteCode::LoadConst(id)),
+                    n => self.bytecode.push(ByteCode::MakeEnum(id, n)),
+                }
+            },
+        }
+
+        true
+    }
+
+    // `&&` and `||` only evaluate the right side when the left side does
+    // not decide the result, the result is always a Bool
+    fn compile_logic(&mut self, bin_op: &BinOp) {
+        let yes = self.store_const(Value::Bool(true));
+        let no = self.store_const(Value::Bool(false));
+        let mut falses = Vec::new();
+        let mut ends = Vec::new();
+
+        self.compile_node(&bin_op.left);
+        let left_false = self.emit_jump(ByteCode::JumpIfFalse(0));
+
+        if bin_op.op == Op::Or {
+            self.bytecode.push(ByteCode::LoadConst(yes));
+            ends.push(self.emit_jump(ByteCode::Jump(0)));
+            self.patch_jump(left_false);
+        } else {
+            falses.push(left_false);
+        }
+
+        self.compile_node(&bin_op.right);
+        falses.push(self.emit_jump(ByteCode::JumpIfFalse(0)));
+        self.bytecode.push(ByteCode::LoadConst(yes));
+        ends.push(self.emit_jump(ByteCode::Jump(0)));
+
+        for at in falses {
+            self.patch_jump(at);
+        }
+
+        self.bytecode.push(ByteCode::LoadConst(no));
+
+        for at in ends {
+            self.patch_jump(at);
+        }
+    }
+
+    // The iterated array and the next index stay on the stack while the
+    // loop runs, each round binds the item in a scope of its own.
+    // Returns the start of the loop and the jump out of it.
+    fn compile_loop_start(&mut self, for_loop: &ForLoop) -> (usize, usize) {
+        self.compile_node(&for_loop.iterator);
+        self.bytecode.push(ByteCode::IterStart);
+
+        let start = self.bytecode.len();
+        let jump_end = self.emit_jump(ByteCode::IterNext(0));
+
+        self.loops.push(Loop {
+            start,
+            breaks: Vec::new(),
+            scopes: self.scopes.len(),
+            values: 2,
+        });
+
+        let id = self.store_ident(&for_loop.item);
+        self.push_scope();
+        self.declare(&for_loop.item, false);
+        self.bytecode.push(ByteCode::Define(id));
+
+        (start, jump_end)
+    }
+
+    fn compile_loop_end(&mut self, start: usize, jump_end: usize) {
+        self.pop_scope();
+        self.bytecode.push(ByteCode::Jump(start));
+        self.patch_jump(jump_end);
+
+        if let Some(l) = self.loops.pop() {
+            for jump in l.breaks {
+                self.patch_jump(jump);
+            }
+        }
+    }
+
     fn compile_node(&mut self, node: &ASTNode) {
-        match node {
-            ASTNode::Ident(ident) => {
-                let id = self.store_ident(&ident);
+        match &node.kind {
+            NodeKind::Ident(ident) => {
+                self.capture(ident);
+
+                let id = self.store_ident(ident);
                 self.bytecode.push(ByteCode::Load(id));
             },
-            ASTNode::Assign(asg) => {
-                self.compile_node(&asg.left);
+            NodeKind::Assign(asg) => {
+                let name = match &asg.left.kind {
+                    NodeKind::Ident(name) => name,
+                    NodeKind::ProbAccess(prob) => {
+                        self.compile_node(&prob.object);
+                        self.compile_node(&asg.right);
+
+                        let id = self.store_ident(&prob.property);
+                        self.bytecode.push(ByteCode::StoreField(id));
+                        self.bytecode.push(ByteCode::Pop);
+                        return;
+                    },
+                    _ => {
+                        self.error(node, "cannot assign to this expression".to_string());
+                        return;
+                    },
+                };
+
+                if self.is_constant(name) {
+                    self.error(node, format!("cannot assign to constant '{}'", name));
+                }
+
+                // Assigning to an unknown name creates it in the current scope
+                match self.is_declared(name) {
+                    true => self.capture(name),
+                    false => {
+                        self.declare(name, false);
+
+                        // A local function capturing itself needs the
+                        // variable to exist before the function is made
+                        if matches!(asg.right.kind, NodeKind::Fun(_)) && self.scopes.len() > 1 {
+                            let id = self.store_ident(name);
+                            self.load_none();
+                            self.bytecode.push(ByteCode::Define(id));
+                        }
+                    },
+                }
+
                 self.compile_node(&asg.right);
-                self.bytecode.push(ByteCode::Store);
+
+                let id = self.store_ident(name);
+                self.bytecode.push(ByteCode::Store(id));
+            },
+            NodeKind::VarDecl(decl) => {
+                self.compile_node(&decl.value);
+                self.declare(&decl.name, decl.constant);
+
+                let id = self.store_ident(&decl.name);
+                self.bytecode.push(ByteCode::Define(id));
             },
-            ASTNode::StructIns(obj) => {
+            NodeKind::StructIns(obj) => {
+                // The instance stays on the stack while its fields are set
+                let id = self.store_ident(&obj.name);
+                self.bytecode.push(ByteCode::InstanceStruct(id));
 
                 for field in &obj.probs {
                     self.compile_node(&field.value);
                     let id = self.store_ident(&field.name);
-                    self.bytecode.push(ByteCode::StoreField(id));
+                    self.bytecode.push(ByteCode::InitField(id));
                 }
 
-                let id = self.store_ident(&obj.name);
-                self.bytecode.push(ByteCode::Load(id));
+                self.bytecode.push(ByteCode::EndStruct);
             },
-            ASTNode::ForLoop(_) => todo!(),
-            ASTNode::Array(a) => {
-                for item in &a.items {
-                    self.compile_node(&item);
-                }
+            NodeKind::ForLoop(for_loop) => {
+                let (start, jump_end) = self.compile_loop_start(for_loop);
+
+                self.compile_block(&for_loop.body);
+                self.bytecode.push(ByteCode::Pop);
 
-                self.bytecode.push(ByteCode::MakeArray(a.items.len()));
+                self.compile_loop_end(start, jump_end);
             },
-            ASTNode::Call(call) => {
+            NodeKind::Array(a) => {
+                if a.items.iter().any(|item| matches!(item.kind, NodeKind::If(_) | NodeKind::ForLoop(_))) {
+                    // Conditional items and loops add a varying number of
+                    // values so the array is built one item at a time
+                    self.bytecode.push(ByteCode::MakeArray(0));
+
+                    for item in &a.items {
+                        self.compile_array_item(item, 0);
+                    }
+                } else {
+                    for item in &a.items {
+                        self.compile_node(item);
+                    }
+
+                    self.bytecode.push(ByteCode::MakeArray(a.items.len()));
+                }
+            },
+            NodeKind::Call(call) => {
+                if let NodeKind::ProbAccess(prob) = &call.callee.kind {
+                    if self.compile_variant(node, prob, &call.args) {
+                        return;
+                    }
+
+                    // The vm picks the method by the type of the object,
+                    // arrays have builtin methods like `map`
+                    self.compile_node(&prob.object);
+                    for a in &call.args {
+                        self.compile_node(a);
+                    }
+
+                    let id = self.store_ident(&prob.property);
+                    self.bytecode.push(ByteCode::CallMethod(id, call.args.len()));
+                    return;
+                }
+
                 self.compile_node(&call.callee);
                 for a in &call.args {
-                    self.compile_node(&a);
+                    self.compile_node(a);
                 }                
                 self.bytecode.push(ByteCode::Call(call.args.len()))
 
             },
-            ASTNode::TypeDef(_) => { /* We are going to ignore types in compiler for now */},
-            ASTNode::Property(_, _) => todo!(),
-            ASTNode::StructIns(obj) => todo!(),
-            ASTNode::Lit(lit) => {
+            NodeKind::TypeDef(_) => { /* We are going to ignore types in compiler for now */},
+            NodeKind::Property(_, _) => todo!(),
+            NodeKind::Lit(lit) => {
                 let id = self.store_const(lit.clone());
                 self.bytecode.push(ByteCode::LoadConst(id))
             },
-            ASTNode::LiteralPercent(_) => todo!(),
-            ASTNode::Fun(def) => {
-                for p in &def.params {
-                    self.compile_node(&p);
+            NodeKind::Fun(def) => {
+                // The body goes to its own code block, loops around the
+                // function can not be left from inside it
+                let outer = std::mem::take(&mut self.bytecode);
+                let loops = std::mem::take(&mut self.loops);
+                self.scopes.push(HashMap::new());
+                self.funs.push(FunScope {
+                    start: self.scopes.len() - 1,
+                    captures: Vec::new(),
+                });
+
+                // Arguments are on the stack, the last one on top
+                for param in def.params.iter().rev() {
+                    let id = self.store_ident(&param.name);
+                    self.bytecode.push(ByteCode::Define(id));
+                    self.declare(&param.name, false);
                 }
 
-                for item in &def.body {
-                    self.compile_node(&item);
+                // Left out arguments arrive as none and get their default
+                for param in &def.params {
+                    let default = match &param.default {
+                        Some(default) => default,
+                        None => continue,
+                    };
+
+                    let id = self.store_ident(&param.name);
+                    self.bytecode.push(ByteCode::Load(id));
+                    self.load_none();
+                    self.bytecode.push(ByteCode::Eq);
+                    let skip = self.emit_jump(ByteCode::JumpIfFalse(0));
+                    self.compile_node(default);
+                    self.bytecode.push(ByteCode::Store(id));
+                    self.patch_jump(skip);
                 }
 
-                self.bytecode.push(ByteCode::MakeFn(def.params.len()));
+                self.compile_block(&def.body);
+                self.bytecode.push(ByteCode::Return);
+
+                let fun = self.funs.pop().unwrap();
+                self.scopes.pop();
+                self.loops = loops;
+                let body = std::mem::replace(&mut self.bytecode, outer);
+                self.functions.push(body);
+
+                self.bytecode.push(ByteCode::MakeFn(self.functions.len(), def.params.len()));
+
+                for name in &fun.captures {
+                    let id = self.store_ident(name);
+                    self.bytecode.push(ByteCode::Capture(id));
+                }
             },
-            ASTNode::StructDef(def) => {
+            NodeKind::StructDef(def) => {
+                // Defaults are evaluated once when the struct is defined
                 for field in &def.fields {
+                    match &field.default {
+                        Some(default) => self.compile_node(default),
+                        None => self.load_none(),
+                    }
+
                     let id = self.store_ident(&field.name);
                     self.bytecode.push(ByteCode::AddField(id));
                 }
@@ -115,18 +739,37 @@ impl Compiler {
                 let id = self.store_ident(&def.name);
                 self.bytecode.push(ByteCode::CreateStruct(id));
             },
-            ASTNode::Var(def) => {
+            NodeKind::Var(def) => {
 
             },
-            ASTNode::ProbAccess(prob) => {
+            NodeKind::ProbAccess(prob) => {
+                if self.compile_variant(node, prob, &[]) {
+                    return;
+                }
 
+                self.compile_node(&prob.object);
+
+                let id = self.store_ident(&prob.property);
+                self.bytecode.push(ByteCode::LoadField(id));
             }
-            ASTNode::Obj(obj) => todo!("Object literals are not supported yet"),
-            ASTNode::Ret(ret) => {
-                // self.compile_node(bytecode, &ret.value);
-                // bytecode.push(ByteCode::Return);
+            NodeKind::Obj(obj) => todo!("Object literals are not supported yet"),
+            NodeKind::Ret(ret) => {
+                if self.funs.is_empty() {
+                    self.error(node, "return outside of a function".to_string());
+                    return;
+                }
+
+                match ret.value.as_ref() {
+                    Some(value) => self.compile_node(value),
+                    None => self.load_none(),
+                }
+
+                self.bytecode.push(ByteCode::Return);
+            },
+            NodeKind::BinOp(bin_op) if matches!(bin_op.op, Op::And | Op::Or) => {
+                self.compile_logic(bin_op);
             },
-            ASTNode::BinOp(bin_op) => {
+            NodeKind::BinOp(bin_op) => {
                 self.compile_node(&bin_op.left);
                 self.compile_node(&bin_op.right);
 
@@ -135,89 +778,250 @@ impl Compiler {
                     Op::Minus => self.bytecode.push(ByteCode::Sub),
                     Op::Multiply => self.bytecode.push(ByteCode::Mul),
                     Op::Divide => self.bytecode.push(ByteCode::Div),
+                    Op::Modulo => self.bytecode.push(ByteCode::Mod),
+                    Op::Eq => self.bytecode.push(ByteCode::Eq),
+                    Op::NotEq => self.bytecode.push(ByteCode::NotEq),
+                    Op::Lt => self.bytecode.push(ByteCode::Lt),
+                    Op::LtEq => self.bytecode.push(ByteCode::LtEq),
+                    Op::Gt => self.bytecode.push(ByteCode::Gt),
+                    Op::GtEq => self.bytecode.push(ByteCode::GtEq),
+                    Op::And | Op::Or => unreachable!(),
                 }
             },
+            NodeKind::Concat(concat) => {
+                for part in &concat.parts {
+                    self.compile_node(part);
+                }
+
+                self.bytecode.push(ByteCode::Concat(concat.parts.len()));
+            },
+            NodeKind::UnOp(un_op) => {
+                self.compile_node(&un_op.expr);
+
+                match un_op.op {
+                    UnaryOp::Not => self.bytecode.push(ByteCode::Not),
+                    UnaryOp::Negate => self.bytecode.push(ByteCode::Neg),
+                }
+            },
+            NodeKind::If(if_expr) => {
+                self.compile_node(&if_expr.cond);
+                let jump_else = self.emit_jump(ByteCode::JumpIfFalse(0));
+
+                self.compile_scoped_block(&if_expr.body);
+                let jump_end = self.emit_jump(ByteCode::Jump(0));
+
+                self.patch_jump(jump_else);
+
+                match &if_expr.else_body {
+                    Some(else_body) => self.compile_scoped_block(else_body),
+                    None => self.load_none(),
+                }
+
+                self.patch_jump(jump_end);
+            },
+            NodeKind::While(while_loop) => {
+                let start = self.bytecode.len();
+
+                self.compile_node(&while_loop.cond);
+                let jump_end = self.emit_jump(ByteCode::JumpIfFalse(0));
+
+                self.loops.push(Loop {
+                    start,
+                    breaks: Vec::new(),
+                    scopes: self.scopes.len(),
+                    values: 0,
+                });
+
+                self.push_scope();
+                self.compile_block(&while_loop.body);
+                self.bytecode.push(ByteCode::Pop);
+
+                self.compile_loop_end(start, jump_end);
+            },
+            NodeKind::Break => {
+                let l = match self.loops.last() {
+                    Some(l) => l.clone(),
+                    None => return self.error(node, "break outside of a loop".to_string()),
+                };
+
+                self.close_scopes(l.scopes);
+
+                for _ in 0..l.values {
+                    self.bytecode.push(ByteCode::Pop);
+                }
+
+                let jump = self.emit_jump(ByteCode::Jump(0));
+
+                if let Some(l) = self.loops.last_mut() {
+                    l.breaks.push(jump);
+                }
+            },
+            NodeKind::Continue => {
+                let l = match self.loops.last() {
+                    Some(l) => l.clone(),
+                    None => return self.error(node, "continue outside of a loop".to_string()),
+                };
+
+                self.close_scopes(l.scopes);
+
+                self.bytecode.push(ByteCode::Jump(l.start));
+            },
+            NodeKind::Match(m) => self.compile_match(m),
+            NodeKind::EnumDef(def) => self.register_enum(def),
+            NodeKind::Error => unreachable!("parse errors are reported before compiling"),
             
         }
     }
 
-    pub fn compile(mut self, ast: Vec<ASTNode>) -> Self {
+    pub fn compile(mut self, ast: Vec<ASTNode>) -> Result<CompileRes, Vec<CompileError>> {
+        // Top level enums can be used before their declaration
         for node in &ast {
+            if let NodeKind::EnumDef(def) = &node.kind {
+                self.register_enum(def);
+            }
+        }
+
+        // Only the value of the last top level expression is kept
+        for (i, node) in ast.iter().enumerate() {
             self.compile_node(node);
+
+            if is_expr(node) && i + 1 < ast.len() {
+                self.bytecode.push(ByteCode::Pop);
+            }
+        }
+
+        match self.errors.is_empty() {
+            true => Ok(CompileRes {
+                bytecode: self.bytecode,
+                functions: self.functions,
+                consts: self.consts
+                    .into_iter()
+                    .enumerate()
+                    .map(|(id, value)| Const { id, value })
+                    .collect(),
+                idents: self.idents,
+            }),
+            false => Err(self.errors),
         }
+    }
+}
+
+// Floats are compared by their bits so that 0.0 and -0.0 stay apart
+fn same_const(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b))
+        | (Value::Percent(a), Value::Percent(b))
+        | (Value::Px(a), Value::Px(b))
+        | (Value::Deg(a), Value::Deg(b)) => a.to_bits() == b.to_bits(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_const(a, b))
+        },
+        _ => a == b,
+    }
+}
 
-        self
+fn binds(pattern: &Pattern) -> bool {
+    match pattern {
+        Pattern::Bind(_) => true,
+        Pattern::Type(_, Some(inner)) => binds(inner),
+        Pattern::Struct(_, fields) => fields.iter().any(|(_, p)| binds(p)),
+        Pattern::Variant(_, _, payload) => payload.iter().any(binds),
+        _ => false,
     }
 }
 
+fn declares(nodes: &[ASTNode]) -> bool {
+    nodes.iter().any(|node| matches!(node.kind, NodeKind::VarDecl(_)))
+}
+
+// Whether compiling the node leaves a value on the stack
+fn is_expr(node: &ASTNode) -> bool {
+    !matches!(
+        node.kind,
+        NodeKind::Assign(_)
+            | NodeKind::Var(_)
+            | NodeKind::TypeDef(_)
+            | NodeKind::StructDef(_)
+            | NodeKind::Ret(_)
+            | NodeKind::ForLoop(_)
+            | NodeKind::While(_)
+            | NodeKind::Break
+            | NodeKind::Continue
+            | NodeKind::VarDecl(_)
+            | NodeKind::EnumDef(_)
+    )
+}
+
 
 #[cfg(test)]
 mod tests {
+    use crate::parser::Assign;
     use crate::parser::BinOp;
     use crate::parser::Op;
+    use crate::parser::Parser;
     use crate::types::Value;
 
     use super::*;
 
+    fn consts(res: &CompileRes) -> Vec<Value> {
+        res.consts.iter().map(|c| c.value.clone()).collect()
+    }
+
     #[test]
     fn test_assign_num_lit() {
-        let ast = vec![
-            crate::parser::ASTNode::Assign(crate::parser::Assign{
-                left: Box::new(crate::parser::ASTNode::Ident("x".to_string())),
-                right: Box::new(crate::parser::ASTNode::Lit(Value::Int(10)))
-            })
+        let ast: Vec<ASTNode> = vec![
+            crate::parser::NodeKind::Assign(crate::parser::Assign{
+                left: Box::new(crate::parser::NodeKind::Ident("x".to_string()).into()),
+                right: Box::new(crate::parser::NodeKind::Lit(Value::Int(10)).into())
+            }).into()
         ];
 
-        let compiler = Compiler::new().compile(ast);
+        let res = Compiler::new().compile(ast).unwrap();
 
-        assert_eq!(compiler.consts, vec![Value::Int(10)]);
-        assert_eq!(compiler.bytecode, vec![
-            ByteCode::Load(0),
+        assert_eq!(consts(&res), vec![Value::Int(10)]);
+        assert_eq!(res.bytecode, vec![
             ByteCode::LoadConst(0),
-            ByteCode::Store,
+            ByteCode::Store(0),
         ]);
     }
 
     #[test]
     fn test_assign_str_lit() {
-        let ast = vec![
-            crate::parser::ASTNode::Assign(crate::parser::Assign{
-                left: Box::new(crate::parser::ASTNode::Ident("x".to_string())),
-                right: Box::new(crate::parser::ASTNode::Lit(Value::Str("Hello".to_string()))),
-            })
+        let ast: Vec<ASTNode> = vec![
+            crate::parser::NodeKind::Assign(crate::parser::Assign{
+                left: Box::new(crate::parser::NodeKind::Ident("x".to_string()).into()),
+                right: Box::new(crate::parser::NodeKind::Lit(Value::Str("Hello".to_string())).into()),
+            }).into()
         ];
 
-        let compiler = Compiler::new().compile(ast);
-
-        println!("{:?}", compiler);
+        let res = Compiler::new().compile(ast).unwrap();
 
-        assert_eq!(compiler.consts, vec![
+        assert_eq!(consts(&res), vec![
             Value::Str("Hello".to_string())
         ]);
-        assert_eq!(compiler.bytecode, vec![
-            ByteCode::Load(0),
+        assert_eq!(res.bytecode, vec![
             ByteCode::LoadConst(0),
-            ByteCode::Store,
+            ByteCode::Store(0),
         ]);
     }
 
     #[test]
     fn test_simple_binop() {
-        let ast = vec![
-            ASTNode::BinOp(BinOp{
-                left: Box::new(ASTNode::Lit(Value::Int(10))),
-                right: Box::new(ASTNode::Lit(Value::Int(20))),
+        let ast: Vec<ASTNode> = vec![
+            NodeKind::BinOp(BinOp{
+                left: Box::new(NodeKind::Lit(Value::Int(10)).into()),
+                right: Box::new(NodeKind::Lit(Value::Int(20)).into()),
                 op: Op::Plus,
-            })
+            }).into()
         ];
 
-        let compiler = Compiler::new().compile(ast);
+        let res = Compiler::new().compile(ast).unwrap();
 
-        assert_eq!(compiler.consts, vec![
+        assert_eq!(consts(&res), vec![
             Value::Int(10),
             Value::Int(20),
         ]);
-        assert_eq!(compiler.bytecode, vec![
+        assert_eq!(res.bytecode, vec![
             ByteCode::LoadConst(0),
             ByteCode::LoadConst(1),
             ByteCode::Add,
@@ -226,26 +1030,26 @@ mod tests {
 
     #[test]
     fn test_more_complicated_binop() {
-        let ast = vec![
-            ASTNode::BinOp(BinOp{
-                left: Box::new(ASTNode::BinOp(BinOp{
-                    left: Box::new(ASTNode::Lit(Value::Int(10))),
-                    right: Box::new(ASTNode::Lit(Value::Int(20))),
+        let ast: Vec<ASTNode> = vec![
+            NodeKind::BinOp(BinOp{
+                left: Box::new(NodeKind::BinOp(BinOp{
+                    left: Box::new(NodeKind::Lit(Value::Int(10)).into()),
+                    right: Box::new(NodeKind::Lit(Value::Int(20)).into()),
                     op: Op::Plus,
-                })),
-                right: Box::new(ASTNode::Lit(Value::Int(30))),
+                }).into()),
+                right: Box::new(NodeKind::Lit(Value::Int(30)).into()),
                 op: Op::Plus,
-            })
+            }).into()
         ];
 
-        let compiler = Compiler::new().compile(ast);
+        let res = Compiler::new().compile(ast).unwrap();
 
-        assert_eq!(compiler.consts, vec![
+        assert_eq!(consts(&res), vec![
             Value::Int(10),
             Value::Int(20),
             Value::Int(30),
         ]);
-        assert_eq!(compiler.bytecode, vec![
+        assert_eq!(res.bytecode, vec![
             ByteCode::LoadConst(0),
             ByteCode::LoadConst(1),
             ByteCode::Add,
@@ -254,6 +1058,572 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_bool_and_none_consts() {
+        let ast = Parser::new("a = true\nb = false\nc = none").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![
+            Value::Bool(true),
+            Value::Bool(false),
+            Value::None,
+        ]);
+        assert_eq!(res.bytecode, vec![
+            ByteCode::LoadConst(0),
+            ByteCode::Store(0),
+            ByteCode::LoadConst(1),
+            ByteCode::Store(1),
+            ByteCode::LoadConst(2),
+            ByteCode::Store(2),
+        ]);
+    }
+
+    #[test]
+    fn test_comparison_and_logic() {
+        let ast = Parser::new("!done && count >= 10 % 3").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![
+            Value::Bool(true),
+            Value::Bool(false),
+            Value::Int(10),
+            Value::Int(3),
+        ]);
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::Not,
+            ByteCode::JumpIfFalse(11),
+            ByteCode::Load(1),
+            ByteCode::LoadConst(2),
+            ByteCode::LoadConst(3),
+            ByteCode::Mod,
+            ByteCode::GtEq,
+            ByteCode::JumpIfFalse(11),
+            ByteCode::LoadConst(0),
+            ByteCode::Jump(12),
+            ByteCode::LoadConst(1),
+        ]);
+    }
+
+    #[test]
+    fn test_unary_minus() {
+        let ast = Parser::new("-x").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::Neg,
+        ]);
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let ast = Parser::new(r#"a = "x = ${x}""#).parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![
+            Value::Str("x = ".to_string()),
+        ]);
+        assert_eq!(res.bytecode, vec![
+            ByteCode::LoadConst(0),
+            ByteCode::Load(0),
+            ByteCode::Concat(2),
+            ByteCode::Store(1),
+        ]);
+    }
+
+    #[test]
+    fn test_if_else() {
+        let ast = Parser::new("if a { 1 } else { b = 2 }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![
+            Value::Int(1),
+            Value::Int(2),
+            Value::None,
+        ]);
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::JumpIfFalse(4),
+            ByteCode::LoadConst(0),
+            ByteCode::Jump(7),
+            ByteCode::LoadConst(1),
+            ByteCode::Store(1),
+            ByteCode::LoadConst(2),
+        ]);
+    }
+
+    #[test]
+    fn test_conditional_array_items() {
+        let ast = Parser::new("[1, if a { 2 3 }]").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::MakeArray(0),
+            ByteCode::LoadConst(0),
+            ByteCode::ArrayPush(0),
+            ByteCode::Load(0),
+            ByteCode::JumpIfFalse(10),
+            ByteCode::LoadConst(1),
+            ByteCode::ArrayPush(0),
+            ByteCode::LoadConst(2),
+            ByteCode::ArrayPush(0),
+            ByteCode::Jump(10),
+        ]);
+    }
+
+    #[test]
+    fn test_for_loop() {
+        let ast = Parser::new("for x in xs { f(x) }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::IterStart,
+            ByteCode::IterNext(11),
+            ByteCode::PushScope,
+            ByteCode::Define(1),
+            ByteCode::Load(2),
+            ByteCode::Load(1),
+            ByteCode::Call(1),
+            ByteCode::Pop,
+            ByteCode::PopScope,
+            ByteCode::Jump(2),
+        ]);
+    }
+
+    #[test]
+    fn test_consts_are_deduped() {
+        let ast = Parser::new("[1, 1.0, 1, -0.0, 0.0, \"a\", \"a\", 1.0]").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![
+            Value::Int(1),
+            Value::Float(1.0),
+            Value::Float(-0.0),
+            Value::Float(0.0),
+            Value::Str("a".to_string()),
+        ]);
+        assert_eq!(res.consts.iter().map(|c| c.id).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_for_comprehension() {
+        let ast = Parser::new("[0, for x in xs { x }]").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::MakeArray(0),
+            ByteCode::LoadConst(0),
+            ByteCode::ArrayPush(0),
+            ByteCode::Load(0),
+            ByteCode::IterStart,
+            ByteCode::IterNext(12),
+            ByteCode::PushScope,
+            ByteCode::Define(1),
+            ByteCode::Load(1),
+            ByteCode::ArrayPush(2),
+            ByteCode::PopScope,
+            ByteCode::Jump(5),
+        ]);
+    }
+
+    #[test]
+    fn test_while_with_break_and_continue() {
+        let ast = Parser::new("while a { if b { continue } break }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::JumpIfFalse(17),
+            ByteCode::PushScope,
+            ByteCode::Load(1),
+            ByteCode::JumpIfFalse(9),
+            ByteCode::PopScope,
+            ByteCode::Jump(0),
+            ByteCode::LoadConst(0),
+            ByteCode::Jump(10),
+            ByteCode::LoadConst(0),
+            ByteCode::Pop,
+            ByteCode::PopScope,
+            ByteCode::Jump(17),
+            ByteCode::LoadConst(0),
+            ByteCode::Pop,
+            ByteCode::PopScope,
+            ByteCode::Jump(0),
+        ]);
+    }
+
+    #[test]
+    fn test_break_drops_iterator() {
+        let ast = Parser::new("for x in xs { break }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::IterStart,
+            ByteCode::IterNext(13),
+            ByteCode::PushScope,
+            ByteCode::Define(1),
+            ByteCode::PopScope,
+            ByteCode::Pop,
+            ByteCode::Pop,
+            ByteCode::Jump(13),
+            ByteCode::LoadConst(0),
+            ByteCode::Pop,
+            ByteCode::PopScope,
+            ByteCode::Jump(2),
+        ]);
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let ast = Parser::new("a = 1\nbreak").parse().unwrap();
+
+        let errors = Compiler::new().compile(ast).unwrap_err();
+
+        assert_eq!(errors, vec![
+            CompileError {
+                span: 6..11,
+                message: "break outside of a loop".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_fun_default_params() {
+        let ast = Parser::new("f = (a, b: Int = 2) => b").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![Value::None, Value::Int(2)]);
+        assert_eq!(res.bytecode, vec![
+            ByteCode::MakeFn(1, 2),
+            ByteCode::Store(2),
+        ]);
+        assert_eq!(res.functions, vec![
+            vec![
+                ByteCode::Define(0),
+                ByteCode::Define(1),
+                ByteCode::Load(0),
+                ByteCode::LoadConst(0),
+                ByteCode::Eq,
+                ByteCode::JumpIfFalse(8),
+                ByteCode::LoadConst(1),
+                ByteCode::Store(0),
+                ByteCode::Load(0),
+                ByteCode::Return,
+            ],
+        ]);
+    }
+
+    #[test]
+    fn test_nested_functions_and_return() {
+        let ast = Parser::new("f = () => { return () => 1 }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::MakeFn(2, 0),
+            ByteCode::Store(0),
+        ]);
+        assert_eq!(res.functions, vec![
+            vec![
+                ByteCode::LoadConst(0),
+                ByteCode::Return,
+            ],
+            vec![
+                ByteCode::MakeFn(1, 0),
+                ByteCode::Return,
+                ByteCode::LoadConst(1),
+                ByteCode::Return,
+            ],
+        ]);
+    }
+
+    #[test]
+    fn test_closure_captures() {
+        let code = r#"
+            g = 1
+            outer = () => {
+                name = ""
+                return () => {
+                    () => name + g
+                }
+            }
+        "#;
+        let ast = Parser::new(code).parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        // Only the local of `outer` is captured, by both nested functions
+        assert_eq!(res.functions, vec![
+            vec![
+                ByteCode::Load(1),
+                ByteCode::Load(0),
+                ByteCode::Add,
+                ByteCode::Return,
+            ],
+            vec![
+                ByteCode::MakeFn(1, 0),
+                ByteCode::Capture(1),
+                ByteCode::Return,
+            ],
+            vec![
+                ByteCode::LoadConst(1),
+                ByteCode::Store(1),
+                ByteCode::MakeFn(2, 0),
+                ByteCode::Capture(1),
+                ByteCode::Return,
+                ByteCode::LoadConst(2),
+                ByteCode::Return,
+            ],
+        ]);
+    }
+
+    #[test]
+    fn test_return_and_break_outside() {
+        let code = r#"
+            return 1
+            while a {
+                f = () => { break }
+            }
+        "#;
+        let ast = Parser::new(code).parse().unwrap();
+
+        let errors = Compiler::new().compile(ast).unwrap_err();
+        let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+
+        assert_eq!(messages, vec![
+            "return outside of a function".to_string(),
+            "break outside of a loop".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_assign_to_const() {
+        let ast = Parser::new("const a = 1\na = 2").parse().unwrap();
+
+        let errors = Compiler::new().compile(ast).unwrap_err();
+
+        assert_eq!(errors, vec![
+            CompileError {
+                span: 12..17,
+                message: "cannot assign to constant 'a'".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_shadowed_const_is_assignable() {
+        let code = r#"
+            const a = 1
+            if b {
+                let a = 2
+                a = 3
+            }
+        "#;
+        let ast = Parser::new(code).parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::LoadConst(0),
+            ByteCode::Define(0),
+            ByteCode::Load(1),
+            ByteCode::JumpIfFalse(12),
+            ByteCode::PushScope,
+            ByteCode::LoadConst(1),
+            ByteCode::Define(0),
+            ByteCode::LoadConst(2),
+            ByteCode::Store(0),
+            ByteCode::LoadConst(3),
+            ByteCode::PopScope,
+            ByteCode::Jump(13),
+            ByteCode::LoadConst(3),
+        ]);
+    }
+
+    #[test]
+    fn test_const_in_block_ends_with_block() {
+        let ast = Parser::new("if b { const a = 1 }\na = 2").parse().unwrap();
+
+        assert!(Compiler::new().compile(ast).is_ok());
+    }
+
+    #[test]
+    fn test_match() {
+        let ast = Parser::new("match a { Int(n) => n, 1 => 2 }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![
+            Value::Str("Int".to_string()),
+            Value::Int(1),
+            Value::Int(2),
+            Value::None,
+        ]);
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            // Int(n)
+            ByteCode::PushScope,
+            ByteCode::Dup,
+            ByteCode::Dup,
+            ByteCode::TypeOf,
+            ByteCode::LoadConst(0),
+            ByteCode::Eq,
+            ByteCode::JumpIfFalse(13),
+            ByteCode::Define(1),
+            ByteCode::Pop,
+            ByteCode::Load(1),
+            ByteCode::PopScope,
+            ByteCode::Jump(24),
+            // A failed type test leaves the copy on the stack
+            ByteCode::Pop,
+            ByteCode::PopScope,
+            // 1
+            ByteCode::Dup,
+            ByteCode::LoadConst(1),
+            ByteCode::Eq,
+            ByteCode::JumpIfFalse(22),
+            ByteCode::Pop,
+            ByteCode::LoadConst(2),
+            ByteCode::Jump(24),
+            // No match
+            ByteCode::Pop,
+            ByteCode::LoadConst(3),
+        ]);
+    }
+
+    #[test]
+    fn test_builtin_enum_variant() {
+        let ast = Parser::new("Div { dir: FlexDir::Row }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(consts(&res), vec![
+            Value::Enum {
+                ty: "FlexDir".to_string(),
+                variant: "Row".to_string(),
+                payload: vec![],
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_enum_variant_with_payload() {
+        let ast = Parser::new("a = Shape.Circle(1)\nenum Shape { Circle(Int) }").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::LoadConst(0),
+            ByteCode::MakeEnum(1, 1),
+            ByteCode::Store(0),
+        ]);
+    }
+
+    #[test]
+    fn test_enum_variant_errors() {
+        let ast = Parser::new("FlexDir.Diagonal\nFlexDir.Row(1)").parse().unwrap();
+
+        let errors = Compiler::new().compile(ast).unwrap_err();
+
+        assert_eq!(errors, vec![
+            CompileError {
+                span: 0..16,
+                message: "enum 'FlexDir' has no variant 'Diagonal'".to_string(),
+            },
+            CompileError {
+                span: 17..31,
+                message: "variant 'FlexDir.Row' takes 0 values but 1 were given".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_variable_shadows_enum() {
+        let ast = Parser::new("let FlexDir = 1\nFlexDir.Row").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::LoadConst(0),
+            ByteCode::Define(0),
+            ByteCode::Load(0),
+            ByteCode::LoadField(1),
+        ]);
+    }
+
+    #[test]
+    fn test_method_call() {
+        let ast = Parser::new("todos.map(f)").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::Load(1),
+            ByteCode::CallMethod(2, 1),
+        ]);
+    }
+
+    #[test]
+    fn test_assign_field() {
+        let ast = Parser::new("todo.done = true").parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::Load(0),
+            ByteCode::LoadConst(0),
+            ByteCode::StoreField(1),
+            ByteCode::Pop,
+        ]);
+
+        // The parser only assigns to names and fields
+        let call = Parser::new("f()").parse().unwrap().remove(0);
+        let one = Parser::new("1").parse().unwrap().remove(0);
+        let ast = vec![ASTNode::new(NodeKind::Assign(Assign {
+            left: Box::new(call),
+            right: Box::new(one),
+        }), 0..7)];
+
+        let errors = Compiler::new().compile(ast).unwrap_err();
+
+        assert_eq!(errors, vec![
+            CompileError {
+                span: 0..7,
+                message: "cannot assign to this expression".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_struct_instance_order() {
+        let ast = Parser::new(r#"Text { text: "a" }"#).parse().unwrap();
+
+        let res = Compiler::new().compile(ast).unwrap();
+
+        assert_eq!(res.bytecode, vec![
+            ByteCode::InstanceStruct(0),
+            ByteCode::LoadConst(0),
+            ByteCode::InitField(1),
+            ByteCode::EndStruct,
+        ]);
+    }
+
     // #[test]
     // fn 
 }
\ No newline at end of file
@@ -1,4 +1,7 @@
 use crate::types::Value;
+use crate::ui::Div;
+use crate::ui::FlexDir;
+use crate::ui::Text;
 
 
 pub struct ComponentProperty {
@@ -15,9 +18,42 @@ pub trait Object {
 
     fn call(&self, args: Value) {}
 
+    // Called for every field the code stores on the component
+    fn set_property(&mut self, _name: &str, _value: &Value) {}
+
     fn on_construct(&self) {}
 
     fn on_destroy(&self) {}
 }
 
+// Rust enums exposed to the language, their variants are used the same
+// way as variants of enums declared in code, `FlexDir.Row`.
+pub trait Enum: Sized {
+    fn name() -> &'static str;
+
+    fn variants() -> &'static [&'static str];
+
+    fn from_variant(variant: &str) -> Option<Self>;
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Enum { ty, variant, .. } if ty == Self::name() => Self::from_variant(variant),
+            _ => None,
+        }
+    }
+}
+
+// Creates the host side of a component constructed by the code
+pub fn builtin_component(name: &str) -> Option<Box<dyn Object>> {
+    match name {
+        "Div" => Some(Box::new(Div::default())),
+        "Text" => Some(Box::new(Text::default())),
+        _ => None,
+    }
+}
 
+pub fn builtin_enums() -> Vec<(&'static str, &'static [&'static str])> {
+    vec![
+        (FlexDir::name(), FlexDir::variants()),
+    ]
+}
@@ -15,6 +15,7 @@ mod pretty;
 mod donitsi;
 mod components;
 mod compiler;
+mod typecheck;
 
 #[tokio::main]
 async fn main() {
@@ -1,4 +1,7 @@
-use logos::{Logos, skip, Span};
+use std::collections::HashMap;
+use std::fmt;
+
+use logos::{FilterResult, Lexer, Logos, skip, Span};
 
 use crate::types::Value;
 
@@ -8,13 +11,39 @@ enum Token {
 	#[token("\t", skip)]
 	#[token("\n", skip)]
 	#[token("\r", skip)]
+	#[regex(r"//[^\n]*", skip)]
+	#[token("/*", block_comment)]
 	Whitespace,
+	#[regex(r"///[^\n]*", |t| t.slice()[3..].trim().to_string())]
+	DocComment(String),
 	#[token("for")]
 	For,
+	#[token("if")]
+	If,
+	#[token("else")]
+	Else,
+	#[token("in")]
+	In,
+	#[token("while")]
+	While,
+	#[token("break")]
+	Break,
+	#[token("continue")]
+	Continue,
+	#[token("let")]
+	Let,
+	#[token("const")]
+	Const,
+	#[token("match")]
+	Match,
+	#[token("enum")]
+	Enum,
 	#[token("type")]
 	Type,
 	#[token("=>")]
 	Arrow,
+	#[token("->")]
+	ThinArrow,
 	#[token("{")]
 	OpenBrace,
 	#[token("}")]
@@ -37,12 +66,36 @@ enum Token {
 	Dot,
 	#[token("=")]
 	Assign,
-	#[regex(r#""[^"]*""#, |t| t.slice()[1..t.slice().len()-1].to_string())]
-	String(String),
-	#[regex(r"-?[0-9]+", |t| t.slice().parse::<i64>())]
+	#[token("==")]
+	Eq,
+	#[token("!=")]
+	NotEq,
+	#[token("<")]
+	Lt,
+	#[token("<=")]
+	LtEq,
+	#[token(">")]
+	Gt,
+	#[token(">=")]
+	GtEq,
+	#[token("&&")]
+	And,
+	#[token("||")]
+	Or,
+	#[token("!")]
+	Not,
+	#[token("\"", lex_string)]
+	String(Vec<StrPart>),
+	#[regex(r"[0-9][0-9_]*", lex_int)]
+	#[regex(r"0x[0-9a-fA-F_]+", lex_int)]
+	#[regex(r"0b[01_]+", lex_int)]
 	Int(i64),
-	#[regex(r"-?[0-9]*\.[0-9]+", |t| t.slice().parse::<f64>())]
+	#[regex(r"[0-9][0-9_]*\.[0-9_]*([eE][+-]?[0-9_]+)?", lex_float)]
+	#[regex(r"\.[0-9][0-9_]*([eE][+-]?[0-9_]+)?", lex_float)]
+	#[regex(r"[0-9][0-9_]*[eE][+-]?[0-9_]+", lex_float)]
 	Float(f64),
+	#[regex(r"[0-9][0-9_]*(\.[0-9_]*)?(%|px|deg)", lex_unit)]
+	Unit(Value),
 	#[token("struct")]
 	Struct,
 	#[token("Int")]
@@ -53,6 +106,12 @@ enum Token {
 	StringDef,
 	#[token("return")]
 	Ret,
+	#[token("true")]
+	True,
+	#[token("false")]
+	False,
+	#[token("none")]
+	None,
 	#[token("+")]
 	Plus,
 	#[token("-")]
@@ -61,12 +120,202 @@ enum Token {
 	Multiply,
 	#[token("/")]
 	Divide,
-	#[regex(r"[A-Za-z_]+", |t| t.slice().to_string())]
+	#[token("%")]
+	Percent,
+	#[regex(r"[\p{XID_Start}_]\p{XID_Continue}*", |t| t.slice().to_string())]
 	Ident(String),
 	#[error]
 	Error,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+enum StrPart {
+	Text(String),
+	// Source of an interpolated expression and its byte offset in the input
+	Expr(String, usize),
+}
+
+// Returns the length of a string body up to and including the closing
+// quote, `s` starts right after the opening quote.
+fn scan_string(s: &str) -> Option<usize> {
+	let mut i = 0;
+
+	while i < s.len() {
+		let c = s[i..].chars().next()?;
+
+		match c {
+			'"' => return Some(i + 1),
+			'\\' => {
+				i += 1 + s[i + 1..].chars().next()?.len_utf8();
+				continue;
+			}
+			'$' if s[i + 1..].starts_with('{') => {
+				i += 2 + scan_interpolation(&s[i + 2..])? + 1;
+				continue;
+			}
+			_ => {}
+		}
+
+		i += c.len_utf8();
+	}
+
+	None
+}
+
+// Returns the index of the brace closing an interpolation, `s` starts
+// right after the opening `${`.
+fn scan_interpolation(s: &str) -> Option<usize> {
+	let mut depth = 0;
+	let mut i = 0;
+
+	while i < s.len() {
+		let c = s[i..].chars().next()?;
+
+		match c {
+			'{' => depth += 1,
+			'}' if depth == 0 => return Some(i),
+			'}' => depth -= 1,
+			'"' => {
+				i += 1 + scan_string(&s[i + 1..])?;
+				continue;
+			}
+			_ => {}
+		}
+
+		i += c.len_utf8();
+	}
+
+	None
+}
+
+fn lex_string(lex: &mut Lexer<Token>) -> Option<Vec<StrPart>> {
+	let rest = lex.remainder();
+	let offset = lex.span().end;
+
+	let len = match scan_string(rest) {
+		Some(len) => len,
+		None => {
+			lex.bump(rest.len());
+			return None;
+		}
+	};
+
+	lex.bump(len);
+
+	let body = &rest[..len - 1];
+	let mut parts = Vec::new();
+	let mut text = String::new();
+	let mut chars = body.char_indices().peekable();
+
+	while let Some((i, c)) = chars.next() {
+		match c {
+			'\\' => {
+				let escaped = match chars.next()?.1 {
+					'n' => '\n',
+					't' => '\t',
+					'r' => '\r',
+					'0' => '\0',
+					'\\' => '\\',
+					'"' => '"',
+					'$' => '$',
+					'u' => {
+						let (start, _) = chars.next().filter(|(_, c)| *c == '{')?;
+						let end = body[start..].find('}')? + start;
+						let code = u32::from_str_radix(&body[start + 1..end], 16).ok()?;
+
+						while chars.peek().map(|(j, _)| *j <= end) == Some(true) {
+							chars.next();
+						}
+
+						char::from_u32(code)?
+					}
+					_ => return None,
+				};
+
+				text.push(escaped);
+			}
+			'$' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+				let end = i + 2 + scan_interpolation(&body[i + 2..])?;
+
+				if !text.is_empty() {
+					parts.push(StrPart::Text(std::mem::take(&mut text)));
+				}
+
+				parts.push(StrPart::Expr(body[i + 2..end].to_string(), offset + i + 2));
+
+				while chars.peek().map(|(j, _)| *j <= end) == Some(true) {
+					chars.next();
+				}
+			}
+			_ => text.push(c),
+		}
+	}
+
+	if !text.is_empty() || parts.is_empty() {
+		parts.push(StrPart::Text(text));
+	}
+
+	Some(parts)
+}
+
+// Parses decimal, hex (`0xff`) and binary (`0b101`) integers, underscores
+// can be used as separators
+fn lex_int(lex: &mut Lexer<Token>) -> Option<i64> {
+	let s = lex.slice().replace('_', "");
+
+	match s.get(..2) {
+		Some("0x") => i64::from_str_radix(&s[2..], 16).ok(),
+		Some("0b") => i64::from_str_radix(&s[2..], 2).ok(),
+		_ => s.parse().ok(),
+	}
+}
+
+fn lex_float(lex: &mut Lexer<Token>) -> Option<f64> {
+	lex.slice().replace('_', "").parse().ok()
+}
+
+// Numbers with a unit suffix like `50%`, `12px` or `90deg`
+fn lex_unit(lex: &mut Lexer<Token>) -> Option<Value> {
+	let s = lex.slice().replace('_', "");
+	let split = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+	let num = s[..split].parse::<f64>().ok()?;
+
+	match &s[split..] {
+		"%" => Some(Value::Percent(num)),
+		"px" => Some(Value::Px(num)),
+		"deg" => Some(Value::Deg(num)),
+		_ => None,
+	}
+}
+
+// Skips a block comment, block comments can be nested
+fn block_comment(lex: &mut Lexer<Token>) -> FilterResult<()> {
+	let rest = lex.remainder();
+	let mut depth = 1;
+	let mut i = 0;
+
+	while i < rest.len() {
+		if rest[i..].starts_with("/*") {
+			depth += 1;
+			i += 2;
+		} else if rest[i..].starts_with("*/") {
+			depth -= 1;
+			i += 2;
+
+			if depth == 0 {
+				lex.bump(i);
+				return FilterResult::Skip;
+			}
+		} else {
+			i += rest[i..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+		}
+	}
+
+	lex.bump(rest.len());
+
+	FilterResult::Error
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Assign {
 	pub left: Box<ASTNode>,
@@ -77,6 +326,7 @@ pub struct Assign {
 pub struct Property {
 	pub name: String,
 	pub value: Box<ASTNode>,
+	pub doc: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -92,8 +342,62 @@ pub struct Obj {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ForLoop {
+	pub item: String,
 	pub iterator: Box<ASTNode>,
-	pub body: Box<ASTNode>,
+	pub body: Vec<ASTNode>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+	// `_`
+	Wildcard,
+	// Lowercase names bind the value
+	Bind(String),
+	Lit(Value),
+	// `Int`, `Int(n)`, the inner pattern is matched against the same value
+	Type(String, Option<Box<Pattern>>),
+	// `Todo { done: true, name }`
+	Struct(String, Vec<(String, Pattern)>),
+	// `Shape.Circle(r)`, `FlexDir::Row`, the payload is matched in order
+	Variant(String, String, Vec<Pattern>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+	pub pattern: Pattern,
+	pub body: Vec<ASTNode>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Match {
+	pub value: Box<ASTNode>,
+	pub arms: Vec<MatchArm>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct VarDecl {
+	pub name: String,
+	pub value: Box<ASTNode>,
+	pub constant: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct While {
+	pub cond: Box<ASTNode>,
+	pub body: Vec<ASTNode>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct If {
+	pub cond: Box<ASTNode>,
+	pub body: Vec<ASTNode>,
+	// `else if` chains are stored as a single nested if
+	pub else_body: Option<Vec<ASTNode>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Concat {
+	pub parts: Vec<ASTNode>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -114,25 +418,53 @@ pub enum VarType {
 	String,
 	Var(String),
 	StrLit(String),
-	FnDef(Fun),
 	Ident(String),
+	// `Array<Todo>`
+	Generic(String, Vec<VarType>),
+	// `Fn(Int) -> Bool`
+	Fn(Vec<VarType>, Option<Box<VarType>>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct TypeField {
 	pub name: String,
-	pub typ: VarType
+	pub typ: VarType,
+	pub default: Option<Box<ASTNode>>,
+	pub doc: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct StructDef {
 	pub name: String,
 	pub fields: Vec<TypeField>,
+	pub doc: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumVariant {
+	pub name: String,
+	pub payload: Vec<VarType>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumDef {
+	pub name: String,
+	pub variants: Vec<EnumVariant>,
+	pub doc: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Param {
+	pub name: String,
+	pub typ: Option<VarType>,
+	// Used when the caller leaves the argument out
+	pub default: Option<Box<ASTNode>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Fun {
-	pub params: Vec<ASTNode>,
+	pub params: Vec<Param>,
+	pub ret: Option<Box<VarType>>,
 	pub body: Vec<ASTNode>,
 }
 
@@ -140,6 +472,9 @@ pub struct Fun {
 pub struct TypeDef {
 	pub name: String,
 	pub fields: Vec<TypeField>,
+	// `type Name = Type`, aliases have no fields
+	pub alias: Option<VarType>,
+	pub doc: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -160,6 +495,27 @@ pub enum Op {
 	Minus,
 	Multiply,
 	Divide,
+	Modulo,
+	Eq,
+	NotEq,
+	Lt,
+	LtEq,
+	Gt,
+	GtEq,
+	And,
+	Or,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UnaryOp {
+	Not,
+	Negate,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnOp {
+	pub op: UnaryOp,
+	pub expr: Box<ASTNode>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -169,8 +525,34 @@ pub struct BinOp {
 	pub right: Box<ASTNode>,
 }
 
+#[derive(Debug, Clone)]
+pub struct ASTNode {
+	pub kind: NodeKind,
+	pub span: Span,
+}
+
+impl ASTNode {
+	pub fn new(kind: NodeKind, span: Span) -> ASTNode {
+		ASTNode { kind, span }
+	}
+}
+
+// Nodes are compared by their contents only so that the same code
+// parsed from a different location is still equal.
+impl PartialEq for ASTNode {
+	fn eq(&self, other: &Self) -> bool {
+		self.kind == other.kind
+	}
+}
+
+impl From<NodeKind> for ASTNode {
+	fn from(kind: NodeKind) -> Self {
+		ASTNode::new(kind, 0..0)
+	}
+}
+
 #[derive(Debug, PartialEq, Clone)]
-pub enum ASTNode {
+pub enum NodeKind {
 	Ident(String),
 	Assign(Assign),
 	StructIns(StructIns),
@@ -179,7 +561,6 @@ pub enum ASTNode {
 	Call(Call),
 	Property(String, Box<ASTNode>),
 	Lit(Value),
-	LiteralPercent(f64),
 	Fun(Fun),
 	StructDef(StructDef),
 	TypeDef(TypeDef),
@@ -187,7 +568,17 @@ pub enum ASTNode {
 	ProbAccess(ProbAccess),
 	Obj(Obj),
 	Ret(Ret),
-	BinOp(BinOp)
+	BinOp(BinOp),
+	UnOp(UnOp),
+	Concat(Concat),
+	If(If),
+	While(While),
+	Break,
+	Continue,
+	VarDecl(VarDecl),
+	Match(Match),
+	EnumDef(EnumDef),
+	Error,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -195,25 +586,166 @@ pub struct Ret {
 	pub value: Box<Option<ASTNode>>
 }
 
+impl Token {
+	fn describe(&self) -> String {
+		match self {
+			Token::Whitespace => "whitespace".to_string(),
+			Token::DocComment(_) => "doc comment".to_string(),
+			Token::For => "'for'".to_string(),
+			Token::If => "'if'".to_string(),
+			Token::Else => "'else'".to_string(),
+			Token::In => "'in'".to_string(),
+			Token::While => "'while'".to_string(),
+			Token::Break => "'break'".to_string(),
+			Token::Continue => "'continue'".to_string(),
+			Token::Let => "'let'".to_string(),
+			Token::Const => "'const'".to_string(),
+			Token::Match => "'match'".to_string(),
+			Token::Enum => "'enum'".to_string(),
+			Token::Type => "'type'".to_string(),
+			Token::Arrow => "'=>'".to_string(),
+			Token::ThinArrow => "'->'".to_string(),
+			Token::OpenBrace => "'{'".to_string(),
+			Token::CloseBrace => "'}'".to_string(),
+			Token::OpenParen => "'('".to_string(),
+			Token::CloseParen => "')'".to_string(),
+			Token::OpenBracket => "'['".to_string(),
+			Token::CloseBracket => "']'".to_string(),
+			Token::DoubleColon => "'::'".to_string(),
+			Token::Colon => "':'".to_string(),
+			Token::Comma => "','".to_string(),
+			Token::Dot => "'.'".to_string(),
+			Token::Assign => "'='".to_string(),
+			Token::Eq => "'=='".to_string(),
+			Token::NotEq => "'!='".to_string(),
+			Token::Lt => "'<'".to_string(),
+			Token::LtEq => "'<='".to_string(),
+			Token::Gt => "'>'".to_string(),
+			Token::GtEq => "'>='".to_string(),
+			Token::And => "'&&'".to_string(),
+			Token::Or => "'||'".to_string(),
+			Token::Not => "'!'".to_string(),
+			Token::String(_) => "string".to_string(),
+			Token::Int(i) => format!("integer {}", i),
+			Token::Float(f) => format!("float {}", f),
+			Token::Unit(v) => format!("number {}", v),
+			Token::Struct => "'struct'".to_string(),
+			Token::IntDef => "'Int'".to_string(),
+			Token::FloatDef => "'Float'".to_string(),
+			Token::StringDef => "'String'".to_string(),
+			Token::Ret => "'return'".to_string(),
+			Token::True => "'true'".to_string(),
+			Token::False => "'false'".to_string(),
+			Token::None => "'none'".to_string(),
+			Token::Plus => "'+'".to_string(),
+			Token::Minus => "'-'".to_string(),
+			Token::Multiply => "'*'".to_string(),
+			Token::Divide => "'/'".to_string(),
+			Token::Percent => "'%'".to_string(),
+			Token::Ident(ident) => format!("identifier '{}'", ident),
+			Token::Error => "invalid token".to_string(),
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+	pub span: Span,
+	pub line: usize,
+	pub column: usize,
+	pub expected: Vec<String>,
+	pub found: Option<String>,
+	pub snippet: String,
+}
+
+impl ParseError {
+	pub fn message(&self) -> String {
+		let found = match &self.found {
+			Some(found) => found.as_str(),
+			None => "end of input",
+		};
+
+		match self.expected.len() {
+			0 => format!("unexpected {}", found),
+			1 => format!("expected {} but found {}", self.expected[0], found),
+			_ => format!("expected one of {} but found {}", self.expected.join(", "), found),
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "error at {}:{}: {}", self.line, self.column, self.message())?;
+		write!(f, "{}", self.snippet)
+	}
+}
+
+type ParseResult<T> = Result<T, ParseError>;
+
 pub struct Parser {
 	tokens: Vec<(Token, Span)>,
 	i: usize,
 	loglevel: usize,
 	callstack: Vec<String>,
-	input: String
+	input: String,
+	errors: Vec<ParseError>,
+	docs: HashMap<usize, String>,
 }
 
 impl Parser {
 	pub fn new(input: &str) -> Parser {
-		let lexer = Token::lexer(input);
+		let (tokens, docs) = Parser::lex(input, 0);
 
 		Parser {
 			input: input.to_string(),
 			i: 0,
 			loglevel: 0,
 			callstack: Vec::new(),
-			tokens: lexer.spanned().map(|(token, span)| (token, span.into())).collect()
+			tokens,
+			errors: Vec::new(),
+			docs,
+		}
+	}
+
+	// Splits the input into tokens whose spans are shifted by `offset`.
+	// Doc comments are kept out of the token stream and attached to the
+	// index of the token that follows them.
+	fn lex(input: &str, offset: usize) -> (Vec<(Token, Span)>, HashMap<usize, String>) {
+		let mut tokens = Vec::new();
+		let mut docs: HashMap<usize, String> = HashMap::new();
+
+		for (token, span) in Token::lexer(input).spanned() {
+			match token {
+				Token::DocComment(doc) => {
+					docs.entry(tokens.len())
+						.and_modify(|d| {
+							d.push('\n');
+							d.push_str(&doc);
+						})
+						.or_insert(doc);
+				}
+				// A unit can not be followed by a name or a digit, `7%3` is
+				// a modulo and `2pxa` a number followed by a name
+				Token::Unit(_) if input[span.end..].starts_with(|c: char| c.is_alphanumeric() || c == '_') => {
+					let split = span.start + input[span.clone()]
+						.find(|c: char| !c.is_ascii_digit() && c != '.' && c != '_')
+						.unwrap();
+					let number = Token::lexer(&input[span.start..split]).next().unwrap_or(Token::Error);
+					tokens.push((number, span.start + offset..split + offset));
+
+					let (rest, rest_docs) = Self::lex(&input[split..], offset + split);
+					for (i, doc) in rest_docs {
+						docs.insert(tokens.len() + i, doc);
+					}
+					tokens.extend(rest);
+
+					break;
+				}
+				_ => tokens.push((token, span.start + offset..span.end + offset)),
+			}
 		}
+
+		(tokens, docs)
 	}
 
 	pub fn set_loglevel(mut self, level: usize) -> Self {
@@ -222,8 +754,22 @@ impl Parser {
 		self
 	}
 
-	pub fn parse(&mut self) -> Vec<ASTNode> {
-		self.parse_block()
+	pub fn parse(&mut self) -> Result<Vec<ASTNode>, Vec<ParseError>> {
+		let (ast, errors) = self.parse_partial();
+
+		if errors.is_empty() {
+			Ok(ast)
+		} else {
+			Err(errors)
+		}
+	}
+
+	// Parses as much as possible, returning the AST with NodeKind::Error
+	// placeholders where items failed together with all recorded errors.
+	pub fn parse_partial(&mut self) -> (Vec<ASTNode>, Vec<ParseError>) {
+		let ast = self.parse_block();
+
+		(ast, std::mem::take(&mut self.errors))
 	}
 
 	fn peek(&self, i: usize) -> Option<Token> {
@@ -237,40 +783,24 @@ impl Parser {
 		}
 	}
 
-	fn peek_unwrap(&self, i: usize) -> Token {
+	fn peek_unwrap(&self, i: usize) -> ParseResult<Token> {
 		match self.peek(i) {
-			Some(token) => token,
-			None => {
-				println!("{}", self.curr_loc());
-				panic!("Unexpected end of input");
-			},
-		}
-	}
-
-	fn eat(&mut self) -> Option<Token> {
-		if self.loglevel > 0 {
-			self.log(&format!("eat: {} val: {:?}", self.i, self.tokens.get(self.i)));
+			Some(token) => Ok(token),
+			None => Err(self.error(&[])),
 		}
-
-		let token = match self.tokens.get(self.i) {
-			Some((token, _)) => token.clone(),
-			None => return None,
-		};
-
-		self.i += 1;
-
-		Some(token.clone())
 	}
 
-	fn expect_eat(&mut self, token: Token) {
+	fn expect_eat(&mut self, token: Token) -> ParseResult<()> {
 		if self.loglevel > 0 {
 			self.log(&format!("expect_eat: {:?}", token));
 		}
 
-		let next = self.eat().unwrap();
-
-		if next != token {
-			panic!("Expected {:?} but got {:?}", token, next);
+		match self.peek(0) {
+			Some(next) if next == token => {
+				self.skip(1);
+				Ok(())
+			}
+			_ => Err(self.error(&[&token.describe()])),
 		}
 	}
 
@@ -282,55 +812,182 @@ impl Parser {
 		self.i += n;
 	}
 
-	fn expect_ident(&mut self) -> String {
+	fn expect_ident(&mut self) -> ParseResult<String> {
 		if self.loglevel > 0 {
-			self.log(&format!("expect_ident"));
+			self.log("expect_ident");
+		}
+
+		match self.peek(0) {
+			Some(Token::Ident(ident)) => {
+				self.skip(1);
+				Ok(ident)
+			}
+			_ => Err(self.error(&["identifier"])),
+		}
+	}
+
+	fn expect_item(&mut self) -> ParseResult<ASTNode> {
+		match self.parse_item()? {
+			Some(node) => Ok(node),
+			None => Err(self.error(&["expression"])),
 		}
+	}
 
-		let token = self.eat().unwrap();
+	// Whether a line break comes before the next token
+	fn at_line_start(&self) -> bool {
+		match (self.i.checked_sub(1).and_then(|i| self.tokens.get(i)), self.tokens.get(self.i)) {
+			(Some((_, prev)), Some((_, next))) => self.input[prev.end..next.start].contains('\n'),
+			_ => false,
+		}
+	}
 
-		match token {
-			Token::Ident(ident) => ident,
-			_ => panic!("Expected ident but got {:?}", token),
+	// Byte offset where the current token starts
+	fn pos(&self) -> usize {
+		match self.tokens.get(self.i) {
+			Some((_, span)) => span.start,
+			None => self.input.len(),
 		}
 	}
 
+	// Creates a node spanning from the byte offset `start` to the end of
+	// the last consumed token.
+	fn node(&self, start: usize, kind: NodeKind) -> ASTNode {
+		let end = match self.i {
+			0 => start,
+			i => self.tokens[i - 1].1.end.max(start),
+		};
+
+		ASTNode::new(kind, start..end)
+	}
+
 	fn log(&self, msg: &str) {
 		println!("{} {}", self.callstack.join(":"), msg);
 	}
 
-	// Return the current location in the source code
-	// takes few lines of context from both sides
-	fn curr_loc(&self) -> String {
-		let mut start = self.i;
-		let mut end = self.i;
+	// Builds an error pointing at the current token, or at the end of
+	// the input if all tokens have been consumed.
+	fn error(&self, expected: &[&str]) -> ParseError {
+		match self.tokens.get(self.i) {
+			Some((token, span)) => self.error_at(span.clone(), Some(token.describe()), expected),
+			None => self.error_at(self.input.len()..self.input.len(), None, expected),
+		}
+	}
 
-		for _ in 0..3 {
-			if start > 0 {
-				start -= 1;
-			}
+	fn error_at(&self, span: Span, found: Option<String>, expected: &[&str]) -> ParseError {
+		let (line, column) = self.line_col(span.start);
+
+		ParseError {
+			snippet: self.snippet(&span),
+			span,
+			line,
+			column,
+			expected: expected.iter().map(|e| e.to_string()).collect(),
+			found,
 		}
+	}
 
-		for _ in 0..3 {
-			if end < self.tokens.len() {
-				end += 1;
-			}
+	// Returns the 1-based line and column of a byte offset
+	fn line_col(&self, offset: usize) -> (usize, usize) {
+		let before = &self.input[..offset];
+		let line = before.matches('\n').count() + 1;
+		let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+		let column = before[line_start..].chars().count() + 1;
+
+		(line, column)
+	}
+
+	// Renders the source line containing the span with a caret
+	// underline below the offending part.
+	fn snippet(&self, span: &Span) -> String {
+		let (line, column) = self.line_col(span.start);
+		let line_start = self.input[..span.start].rfind('\n').map_or(0, |i| i + 1);
+		let text = self.input[line_start..].lines().next().unwrap_or("");
+		let width = self.input[span.start..span.end.min(line_start + text.len()).max(span.start)]
+			.chars()
+			.count()
+			.max(1);
+
+		let gutter = line.to_string();
+		let pad = " ".repeat(gutter.len());
+		let indent: String = text.chars()
+			.take(column - 1)
+			.map(|c| if c == '\t' { '\t' } else { ' ' })
+			.collect();
+
+		format!("{} |\n{} | {}\n{} | {}{}\n", pad, gutter, text, pad, indent, "^".repeat(width))
+	}
+
+	fn record(&mut self, err: ParseError) {
+		// Recovery can stop in front of the same bad token more than once,
+		// report it only the first time.
+		if self.errors.last().map(|last| last.span == err.span) != Some(true) {
+			self.errors.push(err);
+		}
+	}
+
+	// Records the error and skips tokens until `at_sync` reports a safe
+	// point on the nesting level where the failed item started, or until
+	// `close` ends the enclosing construct. Other unbalanced closing tokens
+	// are stray and get skipped.
+	fn recover(&mut self, err: ParseError, start: usize, callstack: usize, close: Option<Token>, at_sync: fn(&Parser) -> bool) {
+		if self.loglevel > 0 {
+			self.callstack.truncate(callstack);
+			self.log(&format!("recover: {}", err.message()));
 		}
 
-		let min = if start > 0 {
-			self.tokens.get(start).unwrap().1.start
-		} else {
-			0
-		};
-		let max = if end < self.tokens.len() {
-			self.tokens.get(end).unwrap().1.end
-		} else {
-			self.input.len()
+		self.record(err);
+
+		// When the enclosing construct is closed at the error position
+		// anything the failed item opened is abandoned.
+		let mut depth = match self.peek(0) == close {
+			true => 0,
+			false => self.tokens[start..self.i]
+				.iter()
+				.fold(0usize, |depth, (token, _)| match token {
+					Token::OpenBrace | Token::OpenBracket | Token::OpenParen => depth + 1,
+					Token::CloseBrace | Token::CloseBracket | Token::CloseParen => depth.saturating_sub(1),
+					_ => depth,
+				}),
 		};
 
-		let text = self.input.get(min..max).unwrap();
+		while let Some(token) = self.peek(0) {
+			match token {
+				Token::OpenBrace | Token::OpenBracket | Token::OpenParen => depth += 1,
+				Token::CloseBrace | Token::CloseBracket | Token::CloseParen => {
+					if depth == 0 && Some(&token) == close.as_ref() {
+						break;
+					}
+					depth = depth.saturating_sub(1);
+				}
+				_ if depth == 0 && self.i > start && at_sync(self) => break,
+				_ => {}
+			}
+
+			self.skip(1);
+		}
+	}
+
+	fn at_item(&self) -> bool {
+		matches!(
+			(self.peek(0), self.peek(1)),
+			(Some(Token::Ident(_)), Some(Token::OpenBrace | Token::Assign))
+				| (Some(Token::Ret | Token::If | Token::For | Token::While | Token::Break | Token::Continue), _)
+				| (Some(Token::Let | Token::Const | Token::Enum | Token::Struct | Token::Type), _)
+		)
+	}
+
+	fn at_property(&self) -> bool {
+		matches!(
+			(self.peek(0), self.peek(1)),
+			(Some(Token::Comma), _) | (Some(Token::Ident(_)), Some(Token::Colon))
+		)
+	}
 
-		text.to_string()
+	fn at_array_item(&self) -> bool {
+		matches!(
+			(self.peek(0), self.peek(1)),
+			(Some(Token::Comma | Token::If | Token::For), _) | (Some(Token::Ident(_)), Some(Token::OpenBrace))
+		)
 	}
 
 	fn parse_block(&mut self) -> Vec<ASTNode> {
@@ -341,10 +998,18 @@ impl Parser {
 		let mut nodes = Vec::new();
 
 		loop {
+			let start = self.i;
+			let pos = self.pos();
+			let callstack = self.callstack.len();
+
 			match self.parse_item() {
-				Some(n) => nodes.push(n),
-				None => break,
-			};
+				Ok(Some(n)) => nodes.push(n),
+				Ok(None) => break,
+				Err(err) => {
+					self.recover(err, start, callstack, None, Parser::at_item);
+					nodes.push(self.node(pos, NodeKind::Error));
+				}
+			}
 		}
 
 		if self.loglevel > 0 {
@@ -354,16 +1019,18 @@ impl Parser {
 		nodes
 	}
 
-	fn parse_item(&mut self) -> Option<ASTNode> {
+	fn parse_item(&mut self) -> ParseResult<Option<ASTNode>> {
 		if self.loglevel > 0 {
 			self.callstack.push("parse_item".to_string());
 		}
 
 		let token = match self.peek(0) {
 			Some(token) => token.clone(),
-			None => return None,
+			None => return Ok(None),
 		};
 
+		let start = self.pos();
+
 		let ret = match token {
 			Token::Ident(ident) => {
 				if self.loglevel > 0 {
@@ -372,193 +1039,323 @@ impl Parser {
 
 				match self.peek(1) {
 					Some(Token::Assign) => {
-						self.skip(2);
+						self.skip(1);
+						let left = self.node(start, NodeKind::Ident(ident.clone()));
+						self.skip(1);
 
 						let a = Assign { 
-							left: Box::new(ASTNode::Ident(ident.clone())), 
-							right: Box::new(self.parse_item().unwrap())
+							left: Box::new(left), 
+							right: Box::new(self.expect_item()?)
 						};
 
 
-						Some(ASTNode::Assign(a))
+						Some(self.node(start, NodeKind::Assign(a)))
 					},
 					Some(Token::Ident(name)) => {
 						self.skip(2);
 						Some(
-							ASTNode::Var(
+							self.node(start, NodeKind::Var(
 								Var {
 									name: name.to_string(),
 									typ: ident.to_string(),
 								}
-							)
+							))
 						)
 					},
 					Some(Token::OpenBrace) => {
-						Some(self.parse_obj_ins())
+						Some(self.parse_obj_ins()?)
 					},
 					Some(Token::Arrow) => {
-						Some(self.parse_fun())
+						Some(self.parse_fun()?)
 					},
 					_ => {
-						Some(self.parse_expr())
-					}
-				}
-			}
-			Token::OpenBracket => {
-				self.skip(1);
-				let mut items = Vec::new();
-
-				while let Some(token) = self.peek(0) {
-					match token {
-						Token::CloseBracket => {
-							self.skip(1);
-							break;
-						},
-						Token::Comma => {
-							self.skip(1);
-						},
-						_ => {
-							items.push(self.parse_item().unwrap());
-						}
+						Some(self.parse_expr()?)
 					}
 				}
-
-				Some(ASTNode::Array(Array { items }))
 			}
+			Token::OpenBracket => Some(self.parse_array()?),
 			Token::OpenParen => {
 				// In here we check if future tokens contain an close paren and an arrow
 				// If so, we parse a function, otherwise we parse an expression
 				let mut i = 1;
+				let mut depth = 1;
 
 				while let Some(token) = self.peek(i) {
 					i += 1;
 
 					match token {
-						Token::CloseParen => break,
+						Token::OpenParen => depth += 1,
+						Token::CloseParen if depth == 1 => break,
+						Token::CloseParen => depth -= 1,
 						_ => {}
 					}
 				};
 		
 				Some(match self.peek(i) {
-					Some(Token::Arrow) => {
-						self.parse_fun()
+					Some(Token::Arrow | Token::ThinArrow) => {
+						self.parse_fun()?
 					}
-					_ => self.parse_expr()
+					_ => self.parse_expr()?
 				})
 			}
+			Token::For => Some(self.parse_for()?),
+			Token::While => Some(self.parse_while()?),
+			Token::Let | Token::Const => Some(self.parse_var_decl()?),
+			Token::Enum => Some(self.parse_enum()?),
+			Token::Struct => Some(self.parse_struct_def()?),
+			Token::Type => Some(self.parse_type_def()?),
+			Token::Break => {
+				self.skip(1);
+				Some(self.node(start, NodeKind::Break))
+			}
+			Token::Continue => {
+				self.skip(1);
+				Some(self.node(start, NodeKind::Continue))
+			}
 			Token::Ret => {
 				self.skip(1);
-				
-				Some(ASTNode::Ret(Ret {
-					value: Box::new(self.parse_item()),
-				}))
+
+				// A bare `return` gives none
+				let value = match self.peek(0) {
+					None | Some(Token::CloseBrace) => None,
+					_ if self.at_line_start() => None,
+					_ => self.parse_item()?,
+				};
+
+				let ret = Ret {
+					value: Box::new(value),
+				};
+
+				Some(self.node(start, NodeKind::Ret(ret)))
 			}
-			_ => Some(self.parse_expr())
+			_ => Some(self.parse_expr()?)
 		};
 
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(ret)
 	}
 
-	fn parse_fun(&mut self) -> ASTNode {
-		if self.loglevel > 0 {
-			self.callstack.push("parse_fun".to_string());
-		}
-
-		let next = match self.peek(0) {
-			Some(token) => token,
-			None => {
-				println!("{}", self.curr_loc());
-				panic!("Expected token but got None")
-			},
-		};
-
-		let mut params = Vec::new();
+	fn parse_array(&mut self) -> ParseResult<ASTNode> {
+		let start = self.pos();
+		self.expect_eat(Token::OpenBracket)?;
 
-		match next {
-			Token::OpenParen => {
-				self.skip(1);
+		let mut items = Vec::new();
 
-				while let Some(token) = self.peek(0) {
-					match token {
-						Token::CloseParen => {
-							self.skip(1);
+		loop {
+			match self.peek_unwrap(0).map_err(|_| self.error(&["']'"]))? {
+				Token::CloseBracket => {
+					self.skip(1);
+					break;
+				},
+				Token::Comma => {
+					self.skip(1);
+				},
+				Token::CloseBrace | Token::CloseParen => {
+					return Err(self.error(&["']'"]));
+				},
+				_ => {
+					let item_start = self.i;
+					let pos = self.pos();
+					let callstack = self.callstack.len();
+
+					match self.expect_item() {
+						Ok(item) => items.push(item),
+						Err(err) => {
+							self.recover(err, item_start, callstack, Some(Token::CloseBracket), Parser::at_array_item);
+							items.push(self.node(pos, NodeKind::Error));
+						}
+					}
+				}
+			}
+		}
+
+		Ok(self.node(start, NodeKind::Array(Array { items })))
+	}
+
+	fn parse_fun(&mut self) -> ParseResult<ASTNode> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_fun".to_string());
+		}
+
+		let start = self.pos();
+		let next = self.peek_unwrap(0)?;
+
+		let mut params = Vec::new();
+
+		match next {
+			Token::OpenParen => {
+				self.skip(1);
+
+				loop {
+					match self.peek(0) {
+						Some(Token::CloseParen) => {
+							self.skip(1);
 							break;
 						},
-						Token::Comma => {
+						Some(Token::Comma) => {
 							self.skip(1);
 						},
-						Token::Ident(name) => {
-							self.skip(1);
-							params.push(ASTNode::Ident(name));
+						Some(Token::Ident(_)) => {
+							params.push(self.parse_param()?);
 						},
-						_ => panic!("Expected ident or ) but got {:?}", self.peek(0)),
+						_ => return Err(self.error(&["identifier", "')'"])),
 					}
 				}
 				
 			}
-			Token::Ident(idt) => {
+			Token::Ident(name) => {
 				self.skip(1);
-				params.push(ASTNode::Ident(idt));
+				params.push(Param { name, typ: None, default: None });
 			}
 			_ => {
-				println!("{}", self.curr_loc());
-				panic!("Expected ( or ident but got {:?}", next);
+				return Err(self.error(&["'('", "identifier"]));
 			}
 		}
 
-		self.expect_eat(Token::Arrow);
+		let ret = match self.peek(0) {
+			Some(Token::ThinArrow) => {
+				self.skip(1);
+				Some(Box::new(self.parse_type()?))
+			},
+			_ => None,
+		};
+
+		self.expect_eat(Token::Arrow)?;
+
+		let body = match self.peek_unwrap(0)? {
+			Token::OpenBrace => self.parse_body()?,
+			_ => vec![self.expect_item()?],
+		};
+
+		let f = Fun {
+			params,
+			ret,
+			body,
+		};
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(self.node(start, NodeKind::Fun(f)))
+	}
+
+	// `name`, `name: Type` or `name: Type = default`
+	fn parse_param(&mut self) -> ParseResult<Param> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_param".to_string());
+		}
 
-		let next = self.peek_unwrap(0);
+		let name = self.expect_ident()?;
 
-		let mut body = Vec::new();
+		let typ = match self.peek(0) {
+			Some(Token::Colon) => {
+				self.skip(1);
+				Some(self.parse_type()?)
+			},
+			_ => None,
+		};
 
-		match next {
-			Token::OpenBrace => {
+		let default = match self.peek(0) {
+			Some(Token::Assign) => {
 				self.skip(1);
-				while let Some(token) = self.peek(0) {
-					match token {
-						Token::CloseBrace => {
-							self.skip(1);
-							break;
-						},
-						_ => body.push(self.parse_item().unwrap()),
+				Some(Box::new(self.parse_expr()?))
+			},
+			_ => None,
+		};
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(Param { name, typ, default })
+	}
+
+	// Parses the items of a `{ ... }` block
+	fn parse_body(&mut self) -> ParseResult<Vec<ASTNode>> {
+		self.expect_eat(Token::OpenBrace)?;
+
+		let mut body = Vec::new();
+
+		loop {
+			match self.peek_unwrap(0).map_err(|_| self.error(&["'}'"]))? {
+				Token::CloseBrace => {
+					self.skip(1);
+					break;
+				},
+				Token::CloseBracket | Token::CloseParen => {
+					return Err(self.error(&["'}'"]));
+				},
+				_ => {
+					let item_start = self.i;
+					let pos = self.pos();
+					let callstack = self.callstack.len();
+
+					match self.expect_item() {
+						Ok(item) => body.push(item),
+						Err(err) => {
+							self.recover(err, item_start, callstack, Some(Token::CloseBrace), Parser::at_item);
+							body.push(self.node(pos, NodeKind::Error));
+						}
 					}
 				}
-			},
-			_ => {
-				body.push(self.parse_item().unwrap());
 			}
 		}
 
-		// self.expect_eat(Token::OpenBrace);
+		Ok(body)
+	}
+
+	fn parse_if(&mut self) -> ParseResult<ASTNode> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_if".to_string());
+		}
+
+		let start = self.pos();
+		self.expect_eat(Token::If)?;
 
-		let f = Fun {
-			params: params,
-			body: body,
+		let cond = self.parse_expr()?;
+		let body = self.parse_body()?;
+
+		let else_body = match self.peek(0) {
+			Some(Token::Else) => {
+				self.skip(1);
+
+				match self.peek(0) {
+					Some(Token::If) => Some(vec![self.parse_if()?]),
+					_ => Some(self.parse_body()?),
+				}
+			}
+			_ => None,
 		};
 
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ASTNode::Fun(f)
+		Ok(self.node(start, NodeKind::If(
+			If {
+				cond: Box::new(cond),
+				body,
+				else_body,
+			}
+		)))
 	}
 
-	fn parse_obj_ins(&mut self) -> ASTNode {
-		let name = self.expect_ident();
-
+	fn parse_match(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
-			self.callstack.push("parse_obj_ins".to_string());
-			self.log(&format!("name: {}", name));
+			self.callstack.push("parse_match".to_string());
 		}
 
-		self.expect_eat(Token::OpenBrace);
+		let start = self.pos();
+		self.expect_eat(Token::Match)?;
 
-		let mut props = Vec::new();
+		let value = self.parse_expr()?;
+		self.expect_eat(Token::OpenBrace)?;
+
+		let mut arms = Vec::new();
 
 		loop {
 			match self.peek(0) {
@@ -569,1056 +1366,3476 @@ impl Parser {
 				Some(Token::Comma) => {
 					self.skip(1);
 				}
+				None => return Err(self.error(&["'}'"])),
 				_ => {
-					let prob_name = self.expect_ident();
-					self.expect_eat(Token::Colon);
+					let pattern = self.parse_pattern()?;
+					self.expect_eat(Token::Arrow)?;
 
-					let prob = Property {
-						name: prob_name,
-						value: Box::new(self.parse_item().unwrap())
+					let body = match self.peek_unwrap(0)? {
+						Token::OpenBrace => self.parse_body()?,
+						_ => vec![self.expect_item()?],
 					};
 
-					props.push(prob);
+					arms.push(MatchArm { pattern, body });
 				}
 			}
 		}
 
-		let b = StructIns {
-			name: name.to_string(),
-			probs: props,
-		};
-
-		ASTNode::StructIns(b)
-	}
-
-	fn parse_expr(&mut self) -> ASTNode {
 		if self.loglevel > 0 {
-			self.callstack.push("parse_expr".to_string());
+			self.callstack.pop();
 		}
 
-		let left = self.parse_term();
-
-		let next = match self.peek(0) {
-			Some(t) => t,
-			None => {
-				if self.loglevel > 0 {
-					self.callstack.pop();
-				}
-				return left;
+		Ok(self.node(start, NodeKind::Match(
+			Match {
+				value: Box::new(value),
+				arms,
 			}
+		)))
+	}
+
+	fn parse_pattern(&mut self) -> ParseResult<Pattern> {
+		let token = match self.peek(0) {
+			Some(token) => token,
+			None => return Err(self.error(&["pattern"])),
 		};
 
-		let ret = match next {
-			Token::Plus => {
-				if self.loglevel > 0 {
-					self.log("Plus");
-				}
+		let lit = match token {
+			Token::Ident(name) if name == "_" => Pattern::Wildcard,
+			Token::Ident(name) if name.starts_with(char::is_uppercase) => {
 				self.skip(1);
-				ASTNode::BinOp(
-					BinOp { 
-						left: Box::new(left), 
-						op: Op::Plus,
-						right: Box::new(self.parse_expr()) 
-					}
-				)
+				return self.parse_type_pattern(name);
+			}
+			Token::Ident(name) => Pattern::Bind(name),
+			Token::IntDef | Token::FloatDef | Token::StringDef => {
+				self.skip(1);
+
+				let name = match token {
+					Token::IntDef => "Int",
+					Token::FloatDef => "Float",
+					_ => "String",
+				};
+
+				return self.parse_type_pattern(name.to_string());
+			}
+			Token::Int(num) => Pattern::Lit(Value::Int(num)),
+			Token::Float(num) => Pattern::Lit(Value::Float(num)),
+			Token::Unit(value) => Pattern::Lit(value),
+			Token::True => Pattern::Lit(Value::Bool(true)),
+			Token::False => Pattern::Lit(Value::Bool(false)),
+			Token::None => Pattern::Lit(Value::None),
+			Token::String(parts) => match parts.as_slice() {
+				[StrPart::Text(text)] => Pattern::Lit(Value::Str(text.clone())),
+				_ => return Err(self.error(&["pattern"])),
 			},
 			Token::Minus => {
-				if self.loglevel > 0 {
-					self.log("Minus");
-				}
+				let value = match self.peek(1) {
+					Some(Token::Int(num)) => Value::Int(-num),
+					Some(Token::Float(num)) => Value::Float(-num),
+					Some(Token::Unit(value)) => value.negate().unwrap(),
+					_ => return Err(self.error(&["pattern"])),
+				};
+
 				self.skip(1);
-				ASTNode::BinOp(
-					BinOp { 
-						left: Box::new(left), 
-						op: Op::Minus,
-						right: Box::new(self.parse_expr()) 
-					}
-				)
-			},
-			Token::OpenParen => {
-				self.parse_call(left)
-			},
-			Token::Dot => {
-				self.parse_prob_access(left)
-			},
-			_ => {
-				left
+				Pattern::Lit(value)
 			}
+			_ => return Err(self.error(&["pattern"])),
 		};
 
-		if self.loglevel > 0 {
-			self.callstack.pop();
-		}
+		self.skip(1);
 
-		ret
+		Ok(lit)
 	}
 
-	fn parse_call(&mut self, caller: ASTNode) -> ASTNode {
-		if self.loglevel > 0 {
-			self.callstack.push("parse_call".to_string());
-		}
+	// Parses what follows the type name of a pattern
+	fn parse_type_pattern(&mut self, name: String) -> ParseResult<Pattern> {
+		match self.peek(0) {
+			Some(Token::OpenParen) => {
+				self.skip(1);
+				let inner = self.parse_pattern()?;
+				self.expect_eat(Token::CloseParen)?;
 
-		self.skip(1);
+				Ok(Pattern::Type(name, Some(Box::new(inner))))
+			}
+			Some(Token::OpenBrace) => {
+				self.skip(1);
+				let mut fields = Vec::new();
 
-		let mut args = Vec::new();
+				loop {
+					match self.peek(0) {
+						Some(Token::CloseBrace) => {
+							self.skip(1);
+							break;
+						}
+						Some(Token::Comma) => {
+							self.skip(1);
+						}
+						Some(Token::Ident(field)) => {
+							self.skip(1);
 
-		while let Some(token) = self.peek(0) {
-			match token {
-				Token::CloseParen => {
-					self.skip(1);
-					break;
-				},
-				Token::Comma => {
+							// `name` alone is short for `name: name`
+							let pattern = match self.peek(0) {
+								Some(Token::Colon) => {
+									self.skip(1);
+									self.parse_pattern()?
+								}
+								_ => Pattern::Bind(field.clone()),
+							};
+
+							fields.push((field, pattern));
+						}
+						_ => return Err(self.error(&["field name", "'}'"])),
+					}
+				}
+
+				Ok(Pattern::Struct(name, fields))
+			}
+			Some(Token::Dot | Token::DoubleColon) => {
+				let variant = match self.peek(1) {
+					Some(Token::Ident(variant)) => variant,
+					_ => {
+						self.skip(1);
+						return Err(self.error(&["variant name"]));
+					}
+				};
+
+				self.skip(2);
+				let mut payload = Vec::new();
+
+				if let Some(Token::OpenParen) = self.peek(0) {
 					self.skip(1);
-				},
-				_ => {
-					args.push(self.parse_item().unwrap());
+
+					loop {
+						match self.peek(0) {
+							Some(Token::CloseParen) => {
+								self.skip(1);
+								break;
+							}
+							Some(Token::Comma) => {
+								self.skip(1);
+							}
+							_ => payload.push(self.parse_pattern()?),
+						}
+					}
 				}
+
+				Ok(Pattern::Variant(name, variant, payload))
 			}
+			_ => Ok(Pattern::Type(name, None)),
 		}
+	}
 
+	fn parse_for(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
-			self.log("call done");
+			self.callstack.push("parse_for".to_string());
 		}
 
-		let call = ASTNode::Call(
-			Call {
-				callee: Box::new(caller),
-				args: args,
-			}
-		);
+		let start = self.pos();
+		self.expect_eat(Token::For)?;
 
-		let ret = match self.peek(0) {
-			Some(t) => match t {
-				Token::OpenParen => {
-					self.parse_call(call)
-				}
-				_ => call,
-			},
-			None => call,
-		};
+		let item = self.expect_ident()?;
+		self.expect_eat(Token::In)?;
+
+		let iterator = self.parse_expr()?;
+		let body = self.parse_body()?;
 
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(self.node(start, NodeKind::ForLoop(
+			ForLoop {
+				item,
+				iterator: Box::new(iterator),
+				body,
+			}
+		)))
 	}
 
-	fn parse_prob_access(&mut self, left: ASTNode) -> ASTNode {
+	fn parse_while(&mut self) -> ParseResult<ASTNode> {
 		if self.loglevel > 0 {
-			self.callstack.push("parse_prob_access".to_string());
+			self.callstack.push("parse_while".to_string());
 		}
 
-		self.skip(1);
+		let start = self.pos();
+		self.expect_eat(Token::While)?;
 
-		let ident = self.expect_ident();
-		
-		let prob_access = ASTNode::ProbAccess(
-			ProbAccess {
-				object: Box::new(left),
-				property: ident,
+		let cond = self.parse_expr()?;
+		let body = self.parse_body()?;
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(self.node(start, NodeKind::While(
+			While {
+				cond: Box::new(cond),
+				body,
 			}
-		);
+		)))
+	}
 
-		let ret = match self.peek(0) {
-			Some(t) => match t {
-				Token::OpenParen => {
-					self.parse_call(prob_access)
-				},
-				_ => prob_access,
-			},
-			None => prob_access,
-		};
+	fn parse_var_decl(&mut self) -> ParseResult<ASTNode> {
+		let start = self.pos();
+		let constant = self.peek(0) == Some(Token::Const);
+		self.skip(1);
+
+		let name = self.expect_ident()?;
+
+		if self.loglevel > 0 {
+			self.callstack.push("parse_var_decl".to_string());
+			self.log(&format!("name: {}", name));
+		}
+
+		self.expect_eat(Token::Assign)?;
+		let value = self.expect_item()?;
 
 		if self.loglevel > 0 {
 			self.callstack.pop();
 		}
 
-		ret
+		Ok(self.node(start, NodeKind::VarDecl(
+			VarDecl {
+				name,
+				value: Box::new(value),
+				constant,
+			}
+		)))
 	}
 
-	fn parse_term(&mut self) -> ASTNode {
+	fn parse_enum(&mut self) -> ParseResult<ASTNode> {
+		let start = self.pos();
+		let doc = self.docs.remove(&self.i);
+		self.expect_eat(Token::Enum)?;
+
+		let name = self.expect_ident()?;
+
 		if self.loglevel > 0 {
-			self.callstack.push("parse_term".to_string());
+			self.callstack.push("parse_enum".to_string());
+			self.log(&format!("name: {}", name));
 		}
 
-		let left = self.parse_factor();
+		self.expect_eat(Token::OpenBrace)?;
 
-		let next = match self.peek(0) {
-			Some(t) => t,
-			None => {
-				if self.loglevel > 0 {
-					self.callstack.pop();
-				}
-				return left;
-			}
-		};
+		let mut variants = Vec::new();
 
-		let ret = match next {
-			Token::Multiply => {
-				if self.loglevel > 0 {
-					self.log("Multiply");
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBrace) => {
+					self.skip(1);
+					break;
 				}
-				self.skip(1);
-				ASTNode::BinOp(
-					BinOp { 
-						left: Box::new(left), 
-						op: Op::Multiply,
-						right: Box::new(self.parse_factor()) 
-					}
-				)
-			},
-			Token::Divide => {
-				if self.loglevel > 0 {
-					self.log("Divide");
+				Some(Token::Comma) => {
+					self.skip(1);
 				}
-				self.skip(1);
-				ASTNode::BinOp(
-					BinOp { 
-						left: Box::new(left), 
-						op: Op::Divide,
-						right: Box::new(self.parse_factor()) 
+				Some(Token::Ident(variant)) => {
+					self.skip(1);
+
+					let mut payload = Vec::new();
+
+					if self.peek(0) == Some(Token::OpenParen) {
+						self.skip(1);
+
+						loop {
+							match self.peek(0) {
+								Some(Token::CloseParen) => {
+									self.skip(1);
+									break;
+								}
+								Some(Token::Comma) => {
+									self.skip(1);
+								}
+								_ => payload.push(self.parse_type()?),
+							}
+						}
 					}
-				)
-			},
-			_ => {
-				left
+
+					variants.push(EnumVariant {
+						name: variant,
+						payload,
+					});
+				}
+				_ => return Err(self.error(&["variant name", "'}'"])),
 			}
-		};
+		}
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(self.node(start, NodeKind::EnumDef(
+			EnumDef {
+				name,
+				variants,
+				doc,
+			}
+		)))
+	}
+
+	fn parse_struct_def(&mut self) -> ParseResult<ASTNode> {
+		let start = self.pos();
+		let doc = self.docs.remove(&self.i);
+		self.expect_eat(Token::Struct)?;
+
+		let name = self.expect_ident()?;
+
+		if self.loglevel > 0 {
+			self.callstack.push("parse_struct_def".to_string());
+			self.log(&format!("name: {}", name));
+		}
+
+		let fields = self.parse_type_fields()?;
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(self.node(start, NodeKind::StructDef(
+			StructDef {
+				name,
+				fields,
+				doc,
+			}
+		)))
+	}
+
+	fn parse_type_def(&mut self) -> ParseResult<ASTNode> {
+		let start = self.pos();
+		let doc = self.docs.remove(&self.i);
+		self.expect_eat(Token::Type)?;
+
+		let name = self.expect_ident()?;
+
+		if self.loglevel > 0 {
+			self.callstack.push("parse_type_def".to_string());
+			self.log(&format!("name: {}", name));
+		}
+
+		let (fields, alias) = match self.peek(0) {
+			Some(Token::Assign) => {
+				self.skip(1);
+				(Vec::new(), Some(self.parse_type()?))
+			}
+			Some(Token::OpenBrace) => (self.parse_type_fields()?, None),
+			_ => return Err(self.error(&["'{'", "'='"])),
+		};
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(self.node(start, NodeKind::TypeDef(
+			TypeDef {
+				name,
+				fields,
+				alias,
+				doc,
+			}
+		)))
+	}
+
+	// Parses `{ name: Type = default, ... }`, the default is optional
+	fn parse_type_fields(&mut self) -> ParseResult<Vec<TypeField>> {
+		self.expect_eat(Token::OpenBrace)?;
+
+		let mut fields = Vec::new();
+
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBrace) => {
+					self.skip(1);
+					break;
+				}
+				Some(Token::Comma) => {
+					self.skip(1);
+				}
+				Some(Token::Ident(name)) => {
+					let doc = self.docs.remove(&self.i);
+
+					self.skip(1);
+					self.expect_eat(Token::Colon)?;
+
+					let typ = self.parse_type()?;
+
+					let default = match self.peek(0) {
+						Some(Token::Assign) => {
+							self.skip(1);
+							Some(Box::new(self.expect_item()?))
+						}
+						_ => None,
+					};
+
+					fields.push(TypeField {
+						name,
+						typ,
+						default,
+						doc,
+					});
+				}
+				_ => return Err(self.error(&["field name", "'}'"])),
+			}
+		}
+
+		Ok(fields)
+	}
+
+	fn parse_type(&mut self) -> ParseResult<VarType> {
+		let typ = match self.peek(0) {
+			Some(Token::IntDef) => VarType::Int,
+			Some(Token::FloatDef) => VarType::Float,
+			Some(Token::StringDef) => VarType::String,
+			Some(Token::Ident(name)) => {
+				self.skip(1);
+
+				return match self.peek(0) {
+					Some(Token::OpenParen) if name == "Fn" => self.parse_fn_type(),
+					Some(Token::Lt) => {
+						self.skip(1);
+						let args = self.parse_type_list(Token::Gt)?;
+						Ok(VarType::Generic(name, args))
+					},
+					_ => Ok(VarType::Ident(name)),
+				};
+			},
+			Some(Token::String(parts)) => match parts.as_slice() {
+				[StrPart::Text(text)] => VarType::StrLit(text.clone()),
+				_ => return Err(self.error(&["type"])),
+			},
+			_ => return Err(self.error(&["type"])),
+		};
+
+		self.skip(1);
+
+		Ok(typ)
+	}
+
+	// `Fn(Int, String) -> Bool`, the return type is optional
+	fn parse_fn_type(&mut self) -> ParseResult<VarType> {
+		self.expect_eat(Token::OpenParen)?;
+		let params = self.parse_type_list(Token::CloseParen)?;
+
+		let ret = match self.peek(0) {
+			Some(Token::ThinArrow) => {
+				self.skip(1);
+				Some(Box::new(self.parse_type()?))
+			},
+			_ => None,
+		};
+
+		Ok(VarType::Fn(params, ret))
+	}
+
+	// Comma separated types up to and including `close`
+	fn parse_type_list(&mut self, close: Token) -> ParseResult<Vec<VarType>> {
+		let mut types = Vec::new();
+
+		loop {
+			match self.peek(0) {
+				Some(token) if token == close => {
+					self.skip(1);
+					break;
+				},
+				Some(Token::Comma) if !types.is_empty() => {
+					self.skip(1);
+				},
+				_ => types.push(self.parse_type()?),
+			}
+		}
+
+		Ok(types)
+	}
+
+	fn parse_obj_ins(&mut self) -> ParseResult<ASTNode> {
+		let start = self.pos();
+		let name = self.expect_ident()?;
+
+		if self.loglevel > 0 {
+			self.callstack.push("parse_obj_ins".to_string());
+			self.log(&format!("name: {}", name));
+		}
+
+		self.expect_eat(Token::OpenBrace)?;
+
+		let mut props = Vec::new();
+
+		loop {
+			match self.peek(0) {
+				Some(Token::CloseBrace) => {
+					self.skip(1);
+					break;
+				}
+				Some(Token::Comma) => {
+					self.skip(1);
+				}
+				None | Some(Token::CloseBracket | Token::CloseParen) => {
+					return Err(self.error(&["'}'"]));
+				}
+				_ => {
+					let prop_start = self.i;
+					let pos = self.pos();
+					let callstack = self.callstack.len();
+
+					match self.parse_property() {
+						Ok(prob) => props.push(prob),
+						Err(err) => {
+							let name = match self.tokens.get(prop_start) {
+								Some((Token::Ident(name), _)) => name.clone(),
+								_ => String::new(),
+							};
+
+							self.recover(err, prop_start, callstack, Some(Token::CloseBrace), Parser::at_property);
+
+							props.push(Property {
+								name,
+								value: Box::new(self.node(pos, NodeKind::Error)),
+								doc: self.docs.remove(&prop_start),
+							});
+						}
+					}
+				}
+			}
+		}
+
+		let b = StructIns {
+			name: name.to_string(),
+			probs: props,
+		};
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(self.node(start, NodeKind::StructIns(b)))
+	}
+
+	fn parse_property(&mut self) -> ParseResult<Property> {
+		let name = match self.peek(0) {
+			Some(Token::Ident(name)) => name,
+			_ => return Err(self.error(&["property name", "'}'"])),
+		};
+
+		let doc = self.docs.remove(&self.i);
+
+		self.skip(1);
+		self.expect_eat(Token::Colon)?;
+
+		Ok(Property {
+			name,
+			value: Box::new(self.expect_item()?),
+			doc,
+		})
+	}
+
+	fn parse_expr(&mut self) -> ParseResult<ASTNode> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_expr".to_string());
+		}
+
+		let mut ret = self.parse_expr_bp(0)?;
+
+		// `todo.done = true` assigns to a field
+		if matches!(ret.kind, NodeKind::ProbAccess(_)) && matches!(self.peek(0), Some(Token::Assign)) {
+			self.skip(1);
+
+			let a = Assign {
+				left: Box::new(ret),
+				right: Box::new(self.expect_item()?),
+			};
+
+			ret = self.node(a.left.span.start, NodeKind::Assign(a));
+		}
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(ret)
+	}
+
+	// Returns the binary operator for a token together with its binding
+	// power, operators with a higher binding power bind tighter.
+	fn infix_op(token: &Token) -> Option<(Op, u8)> {
+		let op = match token {
+			Token::Or => (Op::Or, 1),
+			Token::And => (Op::And, 2),
+			Token::Eq => (Op::Eq, 3),
+			Token::NotEq => (Op::NotEq, 3),
+			Token::Lt => (Op::Lt, 4),
+			Token::LtEq => (Op::LtEq, 4),
+			Token::Gt => (Op::Gt, 4),
+			Token::GtEq => (Op::GtEq, 4),
+			Token::Plus => (Op::Plus, 5),
+			Token::Minus => (Op::Minus, 5),
+			Token::Multiply => (Op::Multiply, 6),
+			Token::Divide => (Op::Divide, 6),
+			Token::Percent => (Op::Modulo, 6),
+			_ => return None,
+		};
+
+		Some(op)
+	}
+
+	// Precedence climbing, only operators binding at least as tight as
+	// `min_bp` are consumed. The right side of an operator is parsed with
+	// a higher minimum which makes chains of equal operators left
+	// associative.
+	fn parse_expr_bp(&mut self, min_bp: u8) -> ParseResult<ASTNode> {
+		let mut left = self.parse_unary()?;
+
+		while let Some((op, bp)) = self.peek(0).as_ref().and_then(Parser::infix_op) {
+			if bp < min_bp {
+				break;
+			}
+
+			if self.loglevel > 0 {
+				self.log(&format!("{:?}", op));
+			}
+
+			self.skip(1);
+			let right = self.parse_expr_bp(bp + 1)?;
+
+			left = self.node(left.span.start, NodeKind::BinOp(
+				BinOp {
+					left: Box::new(left),
+					op,
+					right: Box::new(right),
+				}
+			));
+		}
+
+		Ok(left)
+	}
+
+	// Parses calls and property accesses following an expression
+	fn parse_postfix(&mut self, mut left: ASTNode) -> ParseResult<ASTNode> {
+		loop {
+			left = match self.peek(0) {
+				Some(Token::OpenParen) => self.parse_call(left)?,
+				Some(Token::Dot | Token::DoubleColon) => self.parse_prob_access(left)?,
+				_ => return Ok(left),
+			};
+		}
+	}
+
+	fn parse_call(&mut self, caller: ASTNode) -> ParseResult<ASTNode> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_call".to_string());
+		}
+
+		self.skip(1);
+
+		let mut args = Vec::new();
+
+		loop {
+			match self.peek_unwrap(0).map_err(|_| self.error(&["')'"]))? {
+				Token::CloseParen => {
+					self.skip(1);
+					break;
+				},
+				Token::Comma => {
+					self.skip(1);
+				},
+				_ => {
+					args.push(self.expect_item()?);
+				}
+			}
+		}
+
+		if self.loglevel > 0 {
+			self.log("call done");
+		}
+
+		let call = self.node(caller.span.start, NodeKind::Call(
+			Call {
+				callee: Box::new(caller),
+				args,
+			}
+		));
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(call)
+	}
+
+	fn parse_prob_access(&mut self, left: ASTNode) -> ParseResult<ASTNode> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_prob_access".to_string());
+		}
+
+		self.skip(1);
+
+		let ident = self.expect_ident()?;
+		
+		let prob_access = self.node(left.span.start, NodeKind::ProbAccess(
+			ProbAccess {
+				object: Box::new(left),
+				property: ident,
+			}
+		));
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(prob_access)
+	}
+
+	fn parse_unary(&mut self) -> ParseResult<ASTNode> {
+		let start = self.pos();
+
+		let op = match self.peek(0) {
+			Some(Token::Not) => UnaryOp::Not,
+			Some(Token::Minus) => UnaryOp::Negate,
+			_ => {
+				let factor = self.parse_factor()?;
+				return self.parse_postfix(factor);
+			}
+		};
+
+		if self.loglevel > 0 {
+			self.log(&format!("{:?}", op));
+		}
+
+		self.skip(1);
+
+		// Negative number literals are folded into the literal itself
+		let kind = match (op, self.peek(0)) {
+			(UnaryOp::Negate, Some(Token::Int(num))) => {
+				self.skip(1);
+				NodeKind::Lit(Value::Int(-num))
+			}
+			(UnaryOp::Negate, Some(Token::Float(num))) => {
+				self.skip(1);
+				NodeKind::Lit(Value::Float(-num))
+			}
+			(UnaryOp::Negate, Some(Token::Unit(value))) => {
+				self.skip(1);
+				NodeKind::Lit(value.negate().unwrap())
+			}
+			(op, _) => NodeKind::UnOp(UnOp {
+				op,
+				expr: Box::new(self.parse_unary()?),
+			}),
+		};
+
+		Ok(self.node(start, kind))
+	}
+
+	// Plain strings become literals, interpolated strings become a
+	// concatenation of their text parts and expressions.
+	fn parse_string(&mut self, parts: Vec<StrPart>) -> ParseResult<NodeKind> {
+		if let [StrPart::Text(text)] = parts.as_slice() {
+			return Ok(NodeKind::Lit(Value::Str(text.clone())));
+		}
+
+		let string_span = self.tokens[self.i].1.clone();
+		let mut nodes = Vec::new();
+
+		for part in parts {
+			match part {
+				StrPart::Text(text) => {
+					nodes.push(ASTNode::new(NodeKind::Lit(Value::Str(text)), string_span.clone()));
+				}
+				StrPart::Expr(src, offset) => {
+					let (tokens, _) = Parser::lex(&src, offset);
+
+					// Parse the expression with its own tokens, the input
+					// stays the same so errors point into the string.
+					let outer_tokens = std::mem::replace(&mut self.tokens, tokens);
+					let outer_i = std::mem::replace(&mut self.i, 0);
+
+					let res = self.parse_expr().and_then(|node| match self.i < self.tokens.len() {
+						true => Err(self.error(&["'}'"])),
+						false => Ok(node),
+					});
+
+					self.tokens = outer_tokens;
+					self.i = outer_i;
+
+					let node = match res {
+						Ok(node) => node,
+						Err(err) if err.found.is_none() => {
+							let end = offset + src.len();
+							return Err(self.error_at(end..end, None, &["expression"]));
+						}
+						Err(err) => return Err(err),
+					};
+
+					nodes.push(node);
+				}
+			}
+		}
+
+		Ok(NodeKind::Concat(Concat { parts: nodes }))
+	}
+
+	fn parse_factor(&mut self) -> ParseResult<ASTNode> {
+		if self.loglevel > 0 {
+			self.callstack.push("parse_factor".to_string());
+		}
+
+		let start = self.pos();
+		let next = match self.peek(0) {
+			Some(t) => t,
+			None => return Err(self.error(&["expression"])),
+		};
+
+		let kind = match next {
+			// In an expression `{` may also start the body of a loop or an
+			// if, a struct instance is only assumed when a property follows
+			Token::Ident(_) if matches!(
+				(self.peek(1), self.peek(2), self.peek(3)),
+				(Some(Token::OpenBrace), Some(Token::Ident(_)), Some(Token::Colon))
+			) => {
+				let node = self.parse_obj_ins()?;
+
+				if self.loglevel > 0 {
+					self.callstack.pop();
+				}
+
+				return Ok(node);
+			},
+			Token::Ident(ident) => {
+				if self.loglevel > 0 {
+					self.log(&format!("Ident: {}", ident));
+				}
+
+				NodeKind::Ident(ident.to_string())
+			}
+			Token::String(parts) => self.parse_string(parts)?,
+			Token::Int(num) => NodeKind::Lit(Value::Int(num)),
+			Token::Float(num) => NodeKind::Lit(Value::Float(num)),
+			Token::Unit(value) => NodeKind::Lit(value),
+			Token::True => NodeKind::Lit(Value::Bool(true)),
+			Token::False => NodeKind::Lit(Value::Bool(false)),
+			Token::None => NodeKind::Lit(Value::None),
+			Token::OpenParen => {
+				self.skip(1);
+				let node = self.parse_expr()?;
+				
+				self.expect_eat(Token::CloseParen)?;
+				return Ok(node);
+			},
+			Token::If => {
+				let node = self.parse_if()?;
+
+				if self.loglevel > 0 {
+					self.callstack.pop();
+				}
+
+				return Ok(node);
+			},
+			Token::Match => {
+				let node = self.parse_match()?;
+
+				if self.loglevel > 0 {
+					self.callstack.pop();
+				}
+
+				return Ok(node);
+			},
+			Token::OpenBracket => {
+				let node = self.parse_array()?;
+
+				if self.loglevel > 0 {
+					self.callstack.pop();
+				}
+
+				return Ok(node);
+			},
+			_ => {
+				return Err(self.error(&["expression"]));
+			}
+		};
+
+		self.skip(1);
+
+		if self.loglevel > 0 {
+			self.callstack.pop();
+		}
+
+		Ok(self.node(start, kind))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+	#[test]
+	fn test_simple_plus_expr() {
+		let code = r#"
+			a = 1 + 2
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+								op: Op::Plus,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_simple_minus_expr() {
+		let code = r#"
+			a = 1 - 2
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+								op: Op::Minus,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_simple_mul_expr() {
+		let code = r#"
+			a = 1 * 2
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+								op: Op::Multiply,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_simple_div_expr() {
+		let code = r#"
+			a = 1 / 2
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+								op: Op::Divide,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_paren_expr() {
+		let code = r#"
+			a = (1 + 2) * 3
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+											op: Op::Plus,
+											right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+										}
+									).into()
+								),
+								op: Op::Multiply,
+								right: Box::new(NodeKind::Lit(Value::Int(3)).into()),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_expr_ordering() {
+		let code = r#"
+			a = 1 + 2 * 3
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+								op: Op::Plus,
+								right: Box::new(
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+											op: Op::Multiply,
+											right: Box::new(NodeKind::Lit(Value::Int(3)).into()),
+										}
+									).into()
+								),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_expr_left_associative_minus() {
+		let code = r#"
+			a = 10 - 2 - 3
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(NodeKind::Lit(Value::Int(10)).into()),
+											op: Op::Minus,
+											right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+										}
+									).into()
+								),
+								op: Op::Minus,
+								right: Box::new(NodeKind::Lit(Value::Int(3)).into()),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_expr_long_mixed_chain() {
+		let code = r#"
+			a = 1 + 2 * 3 - 8 / 4 / 2
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+											op: Op::Plus,
+											right: Box::new(
+												NodeKind::BinOp(
+													BinOp {
+														left: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+														op: Op::Multiply,
+														right: Box::new(NodeKind::Lit(Value::Int(3)).into()),
+													}
+												).into()
+											),
+										}
+									).into()
+								),
+								op: Op::Minus,
+								right: Box::new(
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(
+												NodeKind::BinOp(
+													BinOp {
+														left: Box::new(NodeKind::Lit(Value::Int(8)).into()),
+														op: Op::Divide,
+														right: Box::new(NodeKind::Lit(Value::Int(4)).into()),
+													}
+												).into()
+											),
+											op: Op::Divide,
+											right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+										}
+									).into()
+								),
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_expr_long_chain_of_same_operator() {
+		let code = r#"
+			1 + 2 + 3 + 4 + 5
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let mut expected: ASTNode = NodeKind::Lit(Value::Int(1)).into();
+
+		for i in 2..=5 {
+			expected = NodeKind::BinOp(
+				BinOp {
+					left: Box::new(expected),
+					op: Op::Plus,
+					right: Box::new(NodeKind::Lit(Value::Int(i)).into()),
+				}
+			).into();
+		}
+
+		assert_eq!(ast, vec![expected]);
+	}
+
+	#[test]
+	fn test_expr_with_calls_and_access_on_both_sides() {
+		let code = r#"
+			foo(1) * bar.baz - 2
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::BinOp(
+				BinOp {
+					left: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(
+									NodeKind::Call(
+										Call {
+											callee: Box::new(NodeKind::Ident("foo".to_string()).into()),
+											args: vec![
+												NodeKind::Lit(Value::Int(1)).into(),
+											],
+										}
+									).into()
+								),
+								op: Op::Multiply,
+								right: Box::new(
+									NodeKind::ProbAccess(
+										ProbAccess {
+											object: Box::new(NodeKind::Ident("bar".to_string()).into()),
+											property: "baz".to_string(),
+										}
+									).into()
+								),
+							}
+						).into()
+					),
+					op: Op::Minus,
+					right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_call_without_args() {
+		let code = r#"
+			a = foo()
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::Call(
+							Call {
+								callee: Box::new(NodeKind::Ident("foo".to_string()).into()),
+								args: vec![],
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_call_with_num_arg() {
+		let code = r#"
+			a = foo(1)
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expeted: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::Call(
+							Call {
+								callee: Box::new(NodeKind::Ident("foo".to_string()).into()),
+								args: vec![
+									NodeKind::Lit(Value::Int(1)).into(),
+								],
+							}
+						).into()
+					)
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expeted);
+	}
+
+	#[test]
+	fn test_double_call() {
+		let code = r#"
+			a = foo(1)(2)
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::Call(
+							Call {
+								callee: Box::new(
+									NodeKind::Call(
+										Call {
+											callee: Box::new(NodeKind::Ident("foo".to_string()).into()),
+											args: vec![
+												NodeKind::Lit(Value::Int(1)).into(),
+											],
+										}
+									).into()
+								),
+								args: vec![
+									NodeKind::Lit(Value::Int(2)).into(),
+								],
+							}
+
+						).into()
+					),
+				},
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_prob_access() {
+		let code = r#"
+			foo.bar
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::ProbAccess(
+				ProbAccess {
+					object: Box::new(NodeKind::Ident("foo".to_string()).into()),
+					property: "bar".to_string(),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_assign_to_field() {
+		let code = r#"
+			todo.done = true
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(
+						NodeKind::ProbAccess(
+							ProbAccess {
+								object: Box::new(NodeKind::Ident("todo".to_string()).into()),
+								property: "done".to_string(),
+							}
+						).into()
+					),
+					right: Box::new(NodeKind::Lit(Value::Bool(true)).into()),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_method_call() {
+		let code = r#"
+			foo.bar(1)
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Call(
+				Call {
+					callee: Box::new(
+						NodeKind::ProbAccess(
+							ProbAccess {
+								object: Box::new(NodeKind::Ident("foo".to_string()).into()),
+								property: "bar".to_string(),
+							}
+						).into()
+					),
+					args: vec![
+						NodeKind::Lit(Value::Int(1)).into(),
+					],
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_call_with_callback() {
+		let code = r#"
+			foo(() => 5)
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Call(
+				Call {
+					callee: Box::new(NodeKind::Ident("foo".to_string()).into()),
+					args: vec![
+						NodeKind::Fun(
+							Fun {
+								params: vec![],
+								ret: None,
+								body: vec![
+									NodeKind::Lit(Value::Int(5)).into(),
+								],
+							}
+						).into(),
+					],
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_empty_array() {
+		let code = r#"
+			l = []
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("l".to_string()).into()),
+					right: Box::new(
+						NodeKind::Array(
+							Array {
+								items: vec![],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_array_with_many_numbers() {
+		let code = r#"
+			l = [1, 2, 3]
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("l".to_string()).into()),
+					right: Box::new(
+						NodeKind::Array(
+							Array {
+								items: vec![
+									NodeKind::Lit(Value::Int(1)).into(),
+									NodeKind::Lit(Value::Int(2)).into(),
+									NodeKind::Lit(Value::Int(3)).into(),
+								],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_empty_named_instance() {
+		let code = r#"
+			Ball {}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Ball".to_string(),
+					probs: vec![],
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_named_instance_fields() {
+		let code = r#"
+			Ball {
+				x: 1,
+				y: 2,
+				name: "nakki"
+			}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Ball".to_string(),
+					probs: vec![
+						Property {
+							name: "x".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+							doc: None,
+						},
+						Property {
+							name: "y".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+							doc: None,
+						},
+						Property {
+							name: "name".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Str("nakki".to_string())).into()),
+							doc: None,
+						},
+					],
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_fun_empty_param_and_body() {
+		let code = r#"
+			foo = () => {}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("foo".to_string()).into()),
+					right: Box::new(
+						NodeKind::Fun(
+							Fun {
+								params: vec![],
+								ret: None,
+								body: vec![],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_simple_fun() {
+		let code = r#"
+			foo = (a, b) => {
+				a + b
+			}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("foo".to_string()).into()),
+					right: Box::new(
+						NodeKind::Fun(
+							Fun {
+								params: vec![
+									Param { name: "a".to_string(), typ: None, default: None },
+									Param { name: "b".to_string(), typ: None, default: None },
+								],
+								ret: None,
+								body: vec![
+									NodeKind::BinOp(
+										BinOp {
+											op: Op::Plus,
+											left: Box::new(NodeKind::Ident("a".to_string()).into()),
+											right: Box::new(NodeKind::Ident("b".to_string()).into()),
+										}
+									).into()
+								],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_typed_fun() {
+		let code = r#"
+			greet = (name: String, count: Int = 1) -> Bool => {
+				count > 0
+			}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("greet".to_string()).into()),
+					right: Box::new(
+						NodeKind::Fun(
+							Fun {
+								params: vec![
+									Param {
+										name: "name".to_string(),
+										typ: Some(VarType::String),
+										default: None,
+									},
+									Param {
+										name: "count".to_string(),
+										typ: Some(VarType::Int),
+										default: Some(Box::new(NodeKind::Lit(Value::Int(1)).into())),
+									},
+								],
+								ret: Some(Box::new(VarType::Ident("Bool".to_string()))),
+								body: vec![
+									NodeKind::BinOp(
+										BinOp {
+											op: Op::Gt,
+											left: Box::new(NodeKind::Ident("count".to_string()).into()),
+											right: Box::new(NodeKind::Lit(Value::Int(0)).into()),
+										}
+									).into()
+								],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_fun_default_with_call() {
+		let ast = Parser::new("f = (a = g(1), b) -> Int => a")
+			.parse()
+			.unwrap();
+
+		let fun = match &ast[0].kind {
+			NodeKind::Assign(assign) => match &assign.right.kind {
+				NodeKind::Fun(fun) => fun.clone(),
+				other => panic!("expected a function, found {:?}", other),
+			},
+			other => panic!("expected an assignment, found {:?}", other),
+		};
+
+		assert_eq!(fun.params.len(), 2);
+		assert!(matches!(fun.params[0].default.as_deref().map(|d| &d.kind), Some(NodeKind::Call(_))));
+		assert_eq!(fun.ret, Some(Box::new(VarType::Int)));
+	}
+
+	#[test]
+	fn test_fun_param_missing_type() {
+		let errors = Parser::new("f = (a: ) => a")
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors[0].expected, vec!["type".to_string()]);
+		assert_eq!(errors[0].found, Some("')'".to_string()));
+	}
+
+	#[test]
+	fn test_obj_field_fun() {
+		let code = r#"
+			Div {
+				on_click: () => {}
+			}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Div".to_string(),
+					probs: vec![
+						Property {
+							name: "on_click".to_string(),
+							value: Box::new(
+								NodeKind::Fun(
+									Fun {
+										params: vec![],
+										ret: None,
+										body: vec![],
+									}
+								).into()
+							),
+							doc: None,
+						},
+					],
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_fun_without_paren() {
+		let code = r#"
+			foo = a => {
+				a + 1
+			}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("foo".to_string()).into()),
+					right: Box::new(
+						NodeKind::Fun(
+							Fun {
+								params: vec![
+									Param { name: "a".to_string(), typ: None, default: None },
+								],
+								ret: None,
+								body: vec![
+									NodeKind::BinOp(
+										BinOp {
+											op: Op::Plus,
+											left: Box::new(NodeKind::Ident("a".to_string()).into()),
+											right: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+										}
+									).into()
+								],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_fun_without_block() {
+		let code = r#"
+			foo = a => a + 1
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("foo".to_string()).into()),
+					right: Box::new(
+						NodeKind::Fun(
+							Fun {
+								params: vec![
+									Param { name: "a".to_string(), typ: None, default: None },
+								],
+								ret: None,
+								body: vec![
+									NodeKind::BinOp(
+										BinOp {
+											op: Op::Plus,
+											left: Box::new(NodeKind::Ident("a".to_string()).into()),
+											right: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+										}
+									).into()
+								],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_return_expr() {
+		let code = r#"
+			return 1 + 5
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Ret(
+				Ret {
+					value: Box::new(
+						Some(
+							NodeKind::BinOp(
+								BinOp {
+									op: Op::Plus,
+									left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+									right: Box::new(NodeKind::Lit(Value::Int(5)).into()),
+								}
+							).into()
+						)
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_empty_return() {
+		let code = r#"
+			return
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Ret(
+				Ret {
+					value: Box::new(None),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_bare_return_in_body() {
+		let code = r#"
+			f = () => {
+				if a {
+					return
+				}
+				return
+				b = 1
+			}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let ret = || -> ASTNode { NodeKind::Ret(Ret { value: Box::new(None) }).into() };
+
+		let body = match &ast[0].kind {
+			NodeKind::Assign(assign) => match &assign.right.kind {
+				NodeKind::Fun(fun) => fun.body.clone(),
+				kind => panic!("expected a function, got {:?}", kind),
+			},
+			kind => panic!("expected an assignment, got {:?}", kind),
+		};
+
+		assert_eq!(body.len(), 3);
+		assert_eq!(body[0], NodeKind::If(If {
+			cond: Box::new(NodeKind::Ident("a".to_string()).into()),
+			body: vec![ret()],
+			else_body: None,
+		}).into());
+		assert_eq!(body[1], ret());
+	}
+
+	#[test]
+	fn test_parse_obj_instance_in_array() {
+		let code = r#"
+			[
+				Div { }
+			]
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Array(
+				Array {
+					items: vec![
+						NodeKind::StructIns(
+							StructIns {
+								name: "Div".to_string(),
+								probs: vec![],
+							}
+						).into()
+					],
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_parse_vertex() {
+		let code = r#"
+			Vertex { x: -0.6, y: 0.1, color: "black" }
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Vertex".to_string(),
+					probs: vec![
+						Property {
+							name: "x".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Float(-0.6)).into()),
+							doc: None,
+						},
+						Property {
+							name: "y".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Float(0.1)).into()),
+							doc: None,
+						},
+						Property {
+							name: "color".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Str("black".to_string())).into()),
+							doc: None,
+						},
+					],
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+	}
+
+	#[test]
+	fn test_error_unexpected_token() {
+		let code = "a = (1 + 2\nb = 3";
+
+		let errors = Parser::new(code)
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].span, 11..12);
+		assert_eq!(errors[0].line, 2);
+		assert_eq!(errors[0].column, 1);
+		assert_eq!(errors[0].expected, vec!["')'".to_string()]);
+		assert_eq!(errors[0].found, Some("identifier 'b'".to_string()));
+		assert_eq!(errors[0].message(), "expected ')' but found identifier 'b'");
+	}
+
+	#[test]
+	fn test_error_snippet_has_caret() {
+		let code = "Ball {\n  x: 1,\n  y: ]\n}";
+
+		let errors = Parser::new(code)
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors[0].line, 3);
+		assert_eq!(errors[0].column, 6);
+		assert_eq!(errors[0].snippet, "  |\n3 |   y: ]\n  |      ^\n");
+	}
+
+	#[test]
+	fn test_error_snippet_after_non_ascii() {
+		let errors = Parser::new("ä = $")
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors[0].column, 5);
+		assert_eq!(errors[0].snippet, "  |\n1 | ä = $\n  |     ^\n");
+	}
+
+	#[test]
+	fn test_error_unexpected_end_of_input() {
+		let code = "l = [1, 2";
+
+		let errors = Parser::new(code)
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors[0].span, 9..9);
+		assert_eq!(errors[0].found, None);
+		assert_eq!(errors[0].message(), "expected ']' but found end of input");
+	}
+
+	#[test]
+	fn test_error_invalid_character() {
+		let code = "a = 1 $ 2";
+
+		let errors = Parser::new(code)
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors[0].span, 6..7);
+		assert_eq!(errors[0].found, Some("invalid token".to_string()));
+	}
+
+	#[test]
+	fn test_recover_bad_property() {
+		let code = r#"
+			Ball {
+				x: 1
+				y: )
+				name: "nakki"
+			}
+			b = 2
+		"#;
+
+		let (ast, errors) = Parser::new(code)
+			.parse_partial();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Ball".to_string(),
+					probs: vec![
+						Property {
+							name: "x".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+							doc: None,
+						},
+						Property {
+							name: "y".to_string(),
+							value: Box::new(NodeKind::Error.into()),
+							doc: None,
+						},
+						Property {
+							name: "name".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Str("nakki".to_string())).into()),
+							doc: None,
+						},
+					],
+				}
+			).into(),
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("b".to_string()).into()),
+					right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].line, 4);
+	}
+
+	#[test]
+	fn test_recover_bad_array_item() {
+		let code = r#"
+			l = [1, (2 $ 3), 4]
+		"#;
+
+		let (ast, errors) = Parser::new(code)
+			.parse_partial();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("l".to_string()).into()),
+					right: Box::new(
+						NodeKind::Array(
+							Array {
+								items: vec![
+									NodeKind::Lit(Value::Int(1)).into(),
+									NodeKind::Error.into(),
+									NodeKind::Lit(Value::Int(4)).into(),
+								],
+							}
+						).into()
+					),
+				}
+			).into()
+		];
+
+		assert_eq!(ast, expected);
+		assert_eq!(errors.len(), 1);
+	}
+
+	#[test]
+	fn test_recover_reports_all_errors() {
+		let code = r#"
+			a = $
+			Ball { x: 1 }
+			b = ]
+			c = 3
+		"#;
+
+		let (ast, errors) = Parser::new(code)
+			.parse_partial();
+
+		assert_eq!(errors.len(), 2);
+		assert_eq!(errors[0].line, 2);
+		assert_eq!(errors[1].line, 4);
+
+		assert_eq!(ast.len(), 4);
+		assert_eq!(ast[0].kind, NodeKind::Error);
+		assert!(matches!(ast[1].kind, NodeKind::StructIns(_)));
+		assert_eq!(ast[2].kind, NodeKind::Error);
+		assert!(matches!(ast[3].kind, NodeKind::Assign(_)));
+	}
+
+	#[test]
+	fn test_recover_unclosed_array_in_instance() {
+		let code = r#"
+			Div {
+				children: [
+					Text { text: "a" }
+				}
+			Text { text: "b" }
+		"#;
 
-		if self.loglevel > 0 {
-			self.callstack.pop();
-		}
+		let (ast, errors) = Parser::new(code)
+			.parse_partial();
 
-		ret
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].expected, vec!["']'".to_string()]);
+		assert_eq!(ast.len(), 2);
+		assert!(matches!(ast[1].kind, NodeKind::StructIns(_)));
 	}
 
-	fn parse_factor(&mut self) -> ASTNode {
-		if self.loglevel > 0 {
-			self.callstack.push("parse_factor".to_string());
-		}
+	#[test]
+	fn test_node_spans() {
+		let code = "a = 1 + foo(b.c)\nBall { x: [2, 3] }";
 
-		let next = match self.eat() {
-			Some(t) => t,
-			None => {
-				panic!("Unexpected end of tokens");
-			}
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		assert_eq!(ast[0].span, 0..16);
+
+		let assign = match &ast[0].kind {
+			NodeKind::Assign(assign) => assign,
+			_ => panic!("expected assign"),
 		};
 
-		let ret = match next {
-			Token::Ident(ident) => {
-				if self.loglevel > 0 {
-					self.log(&format!("Ident: {}", ident));
-				}
+		assert_eq!(assign.left.span, 0..1);
+		assert_eq!(assign.right.span, 4..16);
 
-				ASTNode::Ident(ident.to_string())
-			}
-			Token::String(s) => ASTNode::Lit(Value::Str(s)),
-			Token::Int(num) => ASTNode::Lit(Value::Int(num)),
-			Token::Float(num) => ASTNode::Lit(Value::Float(num)),
-			Token::OpenParen => {
-				let node = self.parse_expr();
-				
-				self.expect_eat(Token::CloseParen);
-				return node;
-			},
-			_ => {
-				println!("{}", self.curr_loc());
-				panic!("Unexpected token {:?}", next);
-			}
+		let bin_op = match &assign.right.kind {
+			NodeKind::BinOp(bin_op) => bin_op,
+			_ => panic!("expected binop"),
 		};
 
-		if self.loglevel > 0 {
-			self.callstack.pop();
-		}
+		assert_eq!(bin_op.left.span, 4..5);
+		assert_eq!(bin_op.right.span, 8..16);
+
+		assert_eq!(ast[1].span, 17..35);
+
+		let ins = match &ast[1].kind {
+			NodeKind::StructIns(ins) => ins,
+			_ => panic!("expected struct instance"),
+		};
 
-		ret
+		assert_eq!(ins.probs[0].value.span, 27..33);
+		assert_eq!(&code[ins.probs[0].value.span.clone()], "[2, 3]");
 	}
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+	#[test]
+	fn test_error_node_span() {
+		let code = "l = [1, $ 2, 3]";
+
+		let (ast, _) = Parser::new(code)
+			.parse_partial();
+
+		let array = match &ast[0].kind {
+			NodeKind::Assign(Assign { right, .. }) => match &right.kind {
+				NodeKind::Array(array) => array.clone(),
+				_ => panic!("expected array"),
+			},
+			_ => panic!("expected assign"),
+		};
+
+		assert_eq!(array.items[1].kind, NodeKind::Error);
+		assert_eq!(&code[array.items[1].span.clone()], "$ 2");
+	}
 
 	#[test]
-	fn test_simple_plus_expr() {
+	fn test_line_comments() {
 		let code = r#"
-			a = 1 + 2
+			// half of four
+			a = 4 / 2 // trailing note
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
 				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
 					right: Box::new(
-						ASTNode::BinOp(
+						NodeKind::BinOp(
 							BinOp {
-								left: Box::new(ASTNode::Lit(Value::Int(1))),
-								op: Op::Plus,
-								right: Box::new(ASTNode::Lit(Value::Int(2))),
+								left: Box::new(NodeKind::Lit(Value::Int(4)).into()),
+								op: Op::Divide,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
 							}
-						)
+						).into()
 					)
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_simple_minus_expr() {
+	fn test_nested_block_comments() {
 		let code = r#"
-			a = 1 - 2
+			/* outer /* inner */ still comment */
+			l = [1, /* two */ 3]
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
 				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
+					left: Box::new(NodeKind::Ident("l".to_string()).into()),
 					right: Box::new(
-						ASTNode::BinOp(
-							BinOp {
-								left: Box::new(ASTNode::Lit(Value::Int(1))),
-								op: Op::Minus,
-								right: Box::new(ASTNode::Lit(Value::Int(2))),
+						NodeKind::Array(
+							Array {
+								items: vec![
+									NodeKind::Lit(Value::Int(1)).into(),
+									NodeKind::Lit(Value::Int(3)).into(),
+								],
 							}
-						)
-					)
+						).into()
+					),
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_simple_mul_expr() {
+	fn test_unterminated_block_comment() {
+		let code = "a = 1 /* never /* closed */";
+
+		let errors = Parser::new(code)
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].span, 6..27);
+		assert_eq!(errors[0].found, Some("invalid token".to_string()));
+	}
+
+	#[test]
+	fn test_doc_comment_on_property() {
 		let code = r#"
-			a = 1 * 2
+			Window {
+				/// Shown in the title bar
+				/// of the window
+				title: "Todo"
+				// not a doc comment
+				width: 100
+			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
-					right: Box::new(
-						ASTNode::BinOp(
-							BinOp {
-								left: Box::new(ASTNode::Lit(Value::Int(1))),
-								op: Op::Multiply,
-								right: Box::new(ASTNode::Lit(Value::Int(2))),
-							}
-						)
-					)
-				}
-			)
-		];
+		let ins = match &ast[0].kind {
+			NodeKind::StructIns(ins) => ins,
+			_ => panic!("expected struct instance"),
+		};
 
-		assert_eq!(ast, expected);
+		assert_eq!(ins.probs[0].doc, Some("Shown in the title bar\nof the window".to_string()));
+		assert_eq!(ins.probs[1].doc, None);
 	}
 
 	#[test]
-	fn test_simple_div_expr() {
+	fn test_logical_and_comparison_precedence() {
 		let code = r#"
-			a = 1 / 2
+			a = !done && count > 0 || x == 1
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
 				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
 					right: Box::new(
-						ASTNode::BinOp(
+						NodeKind::BinOp(
 							BinOp {
-								left: Box::new(ASTNode::Lit(Value::Int(1))),
-								op: Op::Divide,
-								right: Box::new(ASTNode::Lit(Value::Int(2))),
+								left: Box::new(
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(
+												NodeKind::UnOp(
+													UnOp {
+														op: UnaryOp::Not,
+														expr: Box::new(NodeKind::Ident("done".to_string()).into()),
+													}
+												).into()
+											),
+											op: Op::And,
+											right: Box::new(
+												NodeKind::BinOp(
+													BinOp {
+														left: Box::new(NodeKind::Ident("count".to_string()).into()),
+														op: Op::Gt,
+														right: Box::new(NodeKind::Lit(Value::Int(0)).into()),
+													}
+												).into()
+											),
+										}
+									).into()
+								),
+								op: Op::Or,
+								right: Box::new(
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(NodeKind::Ident("x".to_string()).into()),
+											op: Op::Eq,
+											right: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+										}
+									).into()
+								),
 							}
-						)
+						).into()
 					)
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_paren_expr() {
+	fn test_modulo_binds_tighter_than_equality() {
 		let code = r#"
-			a = (1 + 2) * 3
+			i % 2 != 0
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
-
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
-					right: Box::new(
-						ASTNode::BinOp(
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::BinOp(
+				BinOp {
+					left: Box::new(
+						NodeKind::BinOp(
 							BinOp {
-								left: Box::new(
-									ASTNode::BinOp(
-										BinOp {
-											left: Box::new(ASTNode::Lit(Value::Int(1))),
-											op: Op::Plus,
-											right: Box::new(ASTNode::Lit(Value::Int(2))),
-										}
-									)
-								),
-								op: Op::Multiply,
-								right: Box::new(ASTNode::Lit(Value::Int(3))),
+								left: Box::new(NodeKind::Ident("i".to_string()).into()),
+								op: Op::Modulo,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
 							}
-						)
-					)
+						).into()
+					),
+					op: Op::NotEq,
+					right: Box::new(NodeKind::Lit(Value::Int(0)).into()),
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_expr_ordering() {
+	fn test_unary_minus() {
 		let code = r#"
-			a = 1 + 2 * 3
+			a = -x * 2
+			b = 1-2
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
 				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
 					right: Box::new(
-						ASTNode::BinOp(
+						NodeKind::BinOp(
 							BinOp {
-								left: Box::new(ASTNode::Lit(Value::Int(1))),
-								op: Op::Plus,
-								right: Box::new(
-									ASTNode::BinOp(
-										BinOp {
-											left: Box::new(ASTNode::Lit(Value::Int(2))),
-											op: Op::Multiply,
-											right: Box::new(ASTNode::Lit(Value::Int(3))),
+								left: Box::new(
+									NodeKind::UnOp(
+										UnOp {
+											op: UnaryOp::Negate,
+											expr: Box::new(NodeKind::Ident("x".to_string()).into()),
 										}
-									)
+									).into()
 								),
+								op: Op::Multiply,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
 							}
-						)
+						).into()
 					)
 				}
-			)
+			).into(),
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("b".to_string()).into()),
+					right: Box::new(
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+								op: Op::Minus,
+								right: Box::new(NodeKind::Lit(Value::Int(2)).into()),
+							}
+						).into()
+					)
+				}
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_call_without_args() {
+	fn test_bool_and_none_literals() {
 		let code = r#"
-			a = foo()
+			Checkbox {
+				checked: false
+				enabled: true
+				texture: none
+			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
-					right: Box::new(
-						ASTNode::Call(
-							Call {
-								callee: Box::new(ASTNode::Ident("foo".to_string())),
-								args: vec![],
-							}
-						)
-					)
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Checkbox".to_string(),
+					probs: vec![
+						Property {
+							name: "checked".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Bool(false)).into()),
+							doc: None,
+						},
+						Property {
+							name: "enabled".to_string(),
+							value: Box::new(NodeKind::Lit(Value::Bool(true)).into()),
+							doc: None,
+						},
+						Property {
+							name: "texture".to_string(),
+							value: Box::new(NodeKind::Lit(Value::None).into()),
+							doc: None,
+						},
+					],
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_call_with_num_arg() {
+	fn test_keywords_prefix_identifiers() {
 		let code = r#"
-			a = foo(1)
+			a = nonempty || truely
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expeted = vec![
-			ASTNode::Assign(
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
 				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
 					right: Box::new(
-						ASTNode::Call(
-							Call {
-								callee: Box::new(ASTNode::Ident("foo".to_string())),
-								args: vec![
-									ASTNode::Lit(Value::Int(1)),
-								],
+						NodeKind::BinOp(
+							BinOp {
+								left: Box::new(NodeKind::Ident("nonempty".to_string()).into()),
+								op: Op::Or,
+								right: Box::new(NodeKind::Ident("truely".to_string()).into()),
 							}
-						)
+						).into()
 					)
 				}
-			)
+			).into()
 		];
 
-		assert_eq!(ast, expeted);
+		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_double_call() {
+	fn test_if_else_in_fun_body() {
 		let code = r#"
-			a = foo(1)(2)
+			label = (n) => {
+				if n > 1 {
+					"many"
+				} else if n == 1 {
+					"one"
+				} else {
+					"none"
+				}
+			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
+
+		let lit = |s: &str| -> ASTNode { NodeKind::Lit(Value::Str(s.to_string())).into() };
+		let cmp = |op: Op, n: i64| -> Box<ASTNode> {
+			Box::new(
+				NodeKind::BinOp(
+					BinOp {
+						left: Box::new(NodeKind::Ident("n".to_string()).into()),
+						op,
+						right: Box::new(NodeKind::Lit(Value::Int(n)).into()),
+					}
+				).into()
+			)
+		};
 
-		let expected = vec![
-			ASTNode::Assign(
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
 				Assign {
-					left: Box::new(ASTNode::Ident("a".to_string())),
+					left: Box::new(NodeKind::Ident("label".to_string()).into()),
 					right: Box::new(
-						ASTNode::Call(
-							Call {
-								callee: Box::new(
-									ASTNode::Call(
-										Call {
-											callee: Box::new(ASTNode::Ident("foo".to_string())),
-											args: vec![
-												ASTNode::Lit(Value::Int(1)),
-											],
+						NodeKind::Fun(
+							Fun {
+								params: vec![Param { name: "n".to_string(), typ: None, default: None }],
+								ret: None,
+								body: vec![
+									NodeKind::If(
+										If {
+											cond: cmp(Op::Gt, 1),
+											body: vec![lit("many")],
+											else_body: Some(vec![
+												NodeKind::If(
+													If {
+														cond: cmp(Op::Eq, 1),
+														body: vec![lit("one")],
+														else_body: Some(vec![lit("none")]),
+													}
+												).into()
+											]),
 										}
-									)
-								),
-								args: vec![
-									ASTNode::Lit(Value::Int(2)),
+									).into()
 								],
 							}
-
-						)
+						).into()
 					),
-				},
-			)
+				}
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_prob_access() {
+	fn test_if_in_children() {
 		let code = r#"
-			foo.bar
+			Div {
+				children: [
+					Text {}
+					if show {
+						Div {}
+						Div {}
+					}
+				]
+			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::ProbAccess(
-				ProbAccess {
-					object: Box::new(ASTNode::Ident("foo".to_string())),
-					property: "bar".to_string(),
+		let empty = |name: &str| -> ASTNode {
+			NodeKind::StructIns(StructIns { name: name.to_string(), probs: vec![] }).into()
+		};
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Div".to_string(),
+					probs: vec![
+						Property {
+							name: "children".to_string(),
+							value: Box::new(
+								NodeKind::Array(
+									Array {
+										items: vec![
+											empty("Text"),
+											NodeKind::If(
+												If {
+													cond: Box::new(NodeKind::Ident("show".to_string()).into()),
+													body: vec![empty("Div"), empty("Div")],
+													else_body: None,
+												}
+											).into(),
+										],
+									}
+								).into()
+							),
+							doc: None,
+						},
+					],
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_method_call() {
+	fn test_if_as_property_value() {
+		let ast = Parser::new("Div { width: if wide { 100% } else { 50% } }")
+			.parse()
+			.unwrap();
+
+		let value = match &ast[0].kind {
+			NodeKind::StructIns(ins) => ins.probs[0].value.kind.clone(),
+			_ => panic!("expected instance"),
+		};
+
+		assert_eq!(value, NodeKind::If(
+			If {
+				cond: Box::new(NodeKind::Ident("wide".to_string()).into()),
+				body: vec![NodeKind::Lit(Value::Percent(100.0)).into()],
+				else_body: Some(vec![NodeKind::Lit(Value::Percent(50.0)).into()]),
+			}
+		));
+	}
+
+	#[test]
+	fn test_for_loop() {
 		let code = r#"
-			foo.bar(1)
+			for todo in todos {
+				print(todo)
+			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
-
-		let expected = vec![
-			ASTNode::Call(
-				Call {
-					callee: Box::new(
-						ASTNode::ProbAccess(
-							ProbAccess {
-								object: Box::new(ASTNode::Ident("foo".to_string())),
-								property: "bar".to_string(),
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::ForLoop(
+				ForLoop {
+					item: "todo".to_string(),
+					iterator: Box::new(NodeKind::Ident("todos".to_string()).into()),
+					body: vec![
+						NodeKind::Call(
+							Call {
+								callee: Box::new(NodeKind::Ident("print".to_string()).into()),
+								args: vec![NodeKind::Ident("todo".to_string()).into()],
 							}
-						)
-					),
-					args: vec![
-						ASTNode::Lit(Value::Int(1)),
+						).into()
 					],
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_call_with_callback() {
+	fn test_literals_in_expressions() {
 		let code = r#"
-			foo(() => 5)
+			for x in [1, 2] {
+				print(x)
+			}
+			a = (S { b: 1 })
+			c = "${[1]}"
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Call(
-				Call {
-					callee: Box::new(ASTNode::Ident("foo".to_string())),
-					args: vec![
-						ASTNode::Fun(
-							Fun {
-								params: vec![],
-								body: vec![
-									ASTNode::Lit(Value::Int(5)),
-								],
-							}
-						),
+		let lit = |i: i64| -> ASTNode { NodeKind::Lit(Value::Int(i)).into() };
+		let right = |node: &ASTNode| match &node.kind {
+			NodeKind::Assign(assign) => assign.right.kind.clone(),
+			kind => panic!("expected an assignment, got {:?}", kind),
+		};
+
+		match &ast[0].kind {
+			NodeKind::ForLoop(for_loop) => {
+				assert_eq!(*for_loop.iterator, NodeKind::Array(Array { items: vec![lit(1), lit(2)] }).into());
+				assert_eq!(for_loop.body.len(), 1);
+			},
+			kind => panic!("expected a for loop, got {:?}", kind),
+		}
+
+		assert_eq!(right(&ast[1]), NodeKind::StructIns(StructIns {
+			name: "S".to_string(),
+			probs: vec![Property {
+				name: "b".to_string(),
+				value: Box::new(lit(1)),
+				doc: None,
+			}],
+		}));
+		assert!(matches!(right(&ast[2]), NodeKind::Concat(c) if matches!(c.parts[..], [ASTNode { kind: NodeKind::Array(_), .. }])));
+	}
+
+	#[test]
+	fn test_for_comprehension_in_children() {
+		let code = r#"
+			Div {
+				children: [for t in todos { Text { text: t.name } }]
+			}
+		"#;
+
+		let ast = Parser::new(code)
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructIns(
+				StructIns {
+					name: "Div".to_string(),
+					probs: vec![
+						Property {
+							name: "children".to_string(),
+							value: Box::new(
+								NodeKind::Array(
+									Array {
+										items: vec![
+											NodeKind::ForLoop(
+												ForLoop {
+													item: "t".to_string(),
+													iterator: Box::new(NodeKind::Ident("todos".to_string()).into()),
+													body: vec![
+														NodeKind::StructIns(
+															StructIns {
+																name: "Text".to_string(),
+																probs: vec![
+																	Property {
+																		name: "text".to_string(),
+																		value: Box::new(
+																			NodeKind::ProbAccess(
+																				ProbAccess {
+																					object: Box::new(NodeKind::Ident("t".to_string()).into()),
+																					property: "name".to_string(),
+																				}
+																			).into()
+																		),
+																		doc: None,
+																	},
+																],
+															}
+														).into()
+													],
+												}
+											).into(),
+										],
+									}
+								).into()
+							),
+							doc: None,
+						},
 					],
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_empty_array() {
+	fn test_while_with_break_and_continue() {
 		let code = r#"
-			l = []
+			while running {
+				if paused { continue }
+				break
+			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
-
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("l".to_string())),
-					right: Box::new(
-						ASTNode::Array(
-							Array {
-								items: vec![],
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::While(
+				While {
+					cond: Box::new(NodeKind::Ident("running".to_string()).into()),
+					body: vec![
+						NodeKind::If(
+							If {
+								cond: Box::new(NodeKind::Ident("paused".to_string()).into()),
+								body: vec![NodeKind::Continue.into()],
+								else_body: None,
 							}
-						)
-					),
+						).into(),
+						NodeKind::Break.into(),
+					],
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_array_with_many_numbers() {
+	fn test_let_and_const() {
 		let code = r#"
-			l = [1, 2, 3]
+			let players = [
+				Player { name: "matti" },
+			]
+			const limit = 10
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("l".to_string())),
-					right: Box::new(
-						ASTNode::Array(
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::VarDecl(
+				VarDecl {
+					name: "players".to_string(),
+					value: Box::new(
+						NodeKind::Array(
 							Array {
 								items: vec![
-									ASTNode::Lit(Value::Int(1)),
-									ASTNode::Lit(Value::Int(2)),
-									ASTNode::Lit(Value::Int(3)),
+									NodeKind::StructIns(
+										StructIns {
+											name: "Player".to_string(),
+											probs: vec![
+												Property {
+													name: "name".to_string(),
+													value: Box::new(NodeKind::Lit(Value::Str("matti".to_string())).into()),
+													doc: None,
+												},
+											],
+										}
+									).into(),
 								],
 							}
-						)
+						).into()
 					),
+					constant: false,
 				}
-			)
+			).into(),
+			NodeKind::VarDecl(
+				VarDecl {
+					name: "limit".to_string(),
+					value: Box::new(NodeKind::Lit(Value::Int(10)).into()),
+					constant: true,
+				}
+			).into(),
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_empty_named_instance() {
-		let code = r#"
-			Ball {}
-		"#;
-
-		let ast = Parser::new(code)
-			.parse();
-
-		let expected = vec![
-			ASTNode::StructIns(
-				StructIns {
-					name: "Ball".to_string(),
-					probs: vec![],
-				}
-			)
-		];
+	fn test_let_without_value() {
+		let errors = Parser::new("let x\ny = 1")
+			.parse()
+			.unwrap_err();
 
-		assert_eq!(ast, expected);
+		assert_eq!(errors.len(), 1);
+		assert_eq!(errors[0].expected, vec!["'='".to_string()]);
 	}
 
 	#[test]
-	fn test_named_instance_fields() {
+	fn test_match_patterns() {
 		let code = r#"
-			Ball {
-				x: 1,
-				y: 2,
-				name: "nakki"
+			match value {
+				Int(n) => n,
+				"foo" => 1,
+				-1 => 2,
+				Todo { done: true, name } => name,
+				None => { 3 }
+				Shape.Rect(w, 2) => w,
+				FlexDir::Row => 5,
+				_ => 4
 			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
-
-		let expected = vec![
-			ASTNode::StructIns(
-				StructIns {
-					name: "Ball".to_string(),
-					probs: vec![
-						Property {
-							name: "x".to_string(),
-							value: Box::new(ASTNode::Lit(Value::Int(1))),
+			.parse()
+			.unwrap();
+
+		let int = |n: i64| -> Vec<ASTNode> { vec![NodeKind::Lit(Value::Int(n)).into()] };
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Match(
+				Match {
+					value: Box::new(NodeKind::Ident("value".to_string()).into()),
+					arms: vec![
+						MatchArm {
+							pattern: Pattern::Type("Int".to_string(), Some(Box::new(Pattern::Bind("n".to_string())))),
+							body: vec![NodeKind::Ident("n".to_string()).into()],
 						},
-						Property {
-							name: "y".to_string(),
-							value: Box::new(ASTNode::Lit(Value::Int(2))),
+						MatchArm {
+							pattern: Pattern::Lit(Value::Str("foo".to_string())),
+							body: int(1),
 						},
-						Property {
-							name: "name".to_string(),
-							value: Box::new(ASTNode::Lit(Value::Str("nakki".to_string()))),
+						MatchArm {
+							pattern: Pattern::Lit(Value::Int(-1)),
+							body: int(2),
+						},
+						MatchArm {
+							pattern: Pattern::Struct("Todo".to_string(), vec![
+								("done".to_string(), Pattern::Lit(Value::Bool(true))),
+								("name".to_string(), Pattern::Bind("name".to_string())),
+							]),
+							body: vec![NodeKind::Ident("name".to_string()).into()],
+						},
+						MatchArm {
+							pattern: Pattern::Type("None".to_string(), None),
+							body: int(3),
+						},
+						MatchArm {
+							pattern: Pattern::Variant("Shape".to_string(), "Rect".to_string(), vec![
+								Pattern::Bind("w".to_string()),
+								Pattern::Lit(Value::Int(2)),
+							]),
+							body: vec![NodeKind::Ident("w".to_string()).into()],
+						},
+						MatchArm {
+							pattern: Pattern::Variant("FlexDir".to_string(), "Row".to_string(), vec![]),
+							body: int(5),
+						},
+						MatchArm {
+							pattern: Pattern::Wildcard,
+							body: int(4),
 						},
 					],
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_fun_empty_param_and_body() {
+	fn test_match_invalid_pattern() {
+		let errors = Parser::new("match a { [1] => 2 }")
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors[0].expected, vec!["pattern".to_string()]);
+		assert_eq!(errors[0].found, Some("'['".to_string()));
+	}
+
+	#[test]
+	fn test_enum_def() {
 		let code = r#"
-			foo = () => {}
+			/// Shapes we can draw
+			enum Shape {
+				Circle(Float),
+				Rect(Float, Float)
+				Empty
+			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
-
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("foo".to_string())),
-					right: Box::new(
-						ASTNode::Fun(
-							Fun {
-								params: vec![],
-								body: vec![],
-							}
-						)
-					),
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::EnumDef(
+				EnumDef {
+					name: "Shape".to_string(),
+					variants: vec![
+						EnumVariant {
+							name: "Circle".to_string(),
+							payload: vec![VarType::Float],
+						},
+						EnumVariant {
+							name: "Rect".to_string(),
+							payload: vec![VarType::Float, VarType::Float],
+						},
+						EnumVariant {
+							name: "Empty".to_string(),
+							payload: vec![],
+						},
+					],
+					doc: Some("Shapes we can draw".to_string()),
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_simple_fun() {
-		let code = r#"
-			foo = (a, b) => {
-				a + b
-			}
-		"#;
-
-		let ast = Parser::new(code)
-			.parse();
+	fn test_enum_path_access() {
+		let dot = Parser::new("FlexDir.Row").parse().unwrap();
+		let path = Parser::new("FlexDir::Row").parse().unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("foo".to_string())),
-					right: Box::new(
-						ASTNode::Fun(
-							Fun {
-								params: vec![
-									ASTNode::Ident("a".to_string()),
-									ASTNode::Ident("b".to_string()),
-								],
-								body: vec![
-									ASTNode::BinOp(
-										BinOp {
-											op: Op::Plus,
-											left: Box::new(ASTNode::Ident("a".to_string())),
-											right: Box::new(ASTNode::Ident("b".to_string())),
-										}
-									)
-								],
-							}
-						)
-					),
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::ProbAccess(
+				ProbAccess {
+					object: Box::new(NodeKind::Ident("FlexDir".to_string()).into()),
+					property: "Row".to_string(),
 				}
-			)
+			).into()
 		];
 
-		assert_eq!(ast, expected);
+		assert_eq!(dot, expected);
+		assert_eq!(path, expected);
 	}
 
 	#[test]
-	fn test_obj_field_fun() {
+	fn test_struct_def() {
 		let code = r#"
-			Div {
-				on_click: () => {}
+			/// A thing to do
+			struct Todo {
+				/// Shown in the list
+				title: String
+				done: Bool = false,
+				priority: Int = 1 + 1
 			}
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
-
-		let expected = vec![
-			ASTNode::StructIns(
-				StructIns {
-					name: "Div".to_string(),
-					probs: vec![
-						Property {
-							name: "on_click".to_string(),
-							value: Box::new(
-								ASTNode::Fun(
-									Fun {
-										params: vec![],
-										body: vec![],
+			.parse()
+			.unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::StructDef(
+				StructDef {
+					name: "Todo".to_string(),
+					fields: vec![
+						TypeField {
+							name: "title".to_string(),
+							typ: VarType::String,
+							default: None,
+							doc: Some("Shown in the list".to_string()),
+						},
+						TypeField {
+							name: "done".to_string(),
+							typ: VarType::Ident("Bool".to_string()),
+							default: Some(Box::new(NodeKind::Lit(Value::Bool(false)).into())),
+							doc: None,
+						},
+						TypeField {
+							name: "priority".to_string(),
+							typ: VarType::Int,
+							default: Some(Box::new(
+								NodeKind::BinOp(
+									BinOp {
+										left: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+										op: Op::Plus,
+										right: Box::new(NodeKind::Lit(Value::Int(1)).into()),
 									}
-								)
-							),
+								).into()
+							)),
+							doc: None,
 						},
 					],
+					doc: Some("A thing to do".to_string()),
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_fun_without_paren() {
+	fn test_type_record_and_alias() {
 		let code = r#"
-			foo = a => {
-				a + 1
+			type Todo {
+				id: int
+				title: string
 			}
+			type Id = Int
+			type Align = "start"
 		"#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("foo".to_string())),
-					right: Box::new(
-						ASTNode::Fun(
-							Fun {
-								params: vec![
-									ASTNode::Ident("a".to_string()),
-								],
-								body: vec![
-									ASTNode::BinOp(
-										BinOp {
-											op: Op::Plus,
-											left: Box::new(ASTNode::Ident("a".to_string())),
-											right: Box::new(ASTNode::Lit(Value::Int(1))),
-										}
-									)
-								],
-							}
-						)
-					),
+		let field = |name: &str, typ: &str| TypeField {
+			name: name.to_string(),
+			typ: VarType::Ident(typ.to_string()),
+			default: None,
+			doc: None,
+		};
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::TypeDef(
+				TypeDef {
+					name: "Todo".to_string(),
+					fields: vec![field("id", "int"), field("title", "string")],
+					alias: None,
+					doc: None,
 				}
-			)
+			).into(),
+			NodeKind::TypeDef(
+				TypeDef {
+					name: "Id".to_string(),
+					fields: vec![],
+					alias: Some(VarType::Int),
+					doc: None,
+				}
+			).into(),
+			NodeKind::TypeDef(
+				TypeDef {
+					name: "Align".to_string(),
+					fields: vec![],
+					alias: Some(VarType::StrLit("start".to_string())),
+					doc: None,
+				}
+			).into(),
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_fun_without_block() {
+	fn test_generic_types() {
 		let code = r#"
-			foo = a => a + 1
+			type Todos = Array<Todo>
+			type OnChange = Fn(String, Int)
+			type Filter = Fn(Todo) -> Bool
+			type Nested = Array<Array<Fn()>>
 		"#;
 
-		let ast = Parser::new(code)
-			.parse();
+		let aliases: Vec<VarType> = Parser::new(code)
+			.parse()
+			.unwrap()
+			.into_iter()
+			.map(|node| match node.kind {
+				NodeKind::TypeDef(def) => def.alias.unwrap(),
+				other => panic!("expected a type alias, found {:?}", other),
+			})
+			.collect();
+
+		assert_eq!(aliases, vec![
+			VarType::Generic("Array".to_string(), vec![VarType::Ident("Todo".to_string())]),
+			VarType::Fn(vec![VarType::String, VarType::Int], None),
+			VarType::Fn(
+				vec![VarType::Ident("Todo".to_string())],
+				Some(Box::new(VarType::Ident("Bool".to_string()))),
+			),
+			VarType::Generic("Array".to_string(), vec![
+				VarType::Generic("Array".to_string(), vec![VarType::Fn(vec![], None)]),
+			]),
+		]);
+	}
 
-		let expected = vec![
-			ASTNode::Assign(
-				Assign {
-					left: Box::new(ASTNode::Ident("foo".to_string())),
-					right: Box::new(
-						ASTNode::Fun(
-							Fun {
-								params: vec![
-									ASTNode::Ident("a".to_string()),
-								],
-								body: vec![
-									ASTNode::BinOp(
-										BinOp {
-											op: Op::Plus,
-											left: Box::new(ASTNode::Ident("a".to_string())),
-											right: Box::new(ASTNode::Lit(Value::Int(1))),
-										}
-									)
-								],
-							}
-						)
-					),
-				}
-			)
-		];
+	#[test]
+	fn test_unclosed_generic_type() {
+		let errors = Parser::new("type Todos = Array<Todo")
+			.parse()
+			.unwrap_err();
 
-		assert_eq!(ast, expected);
+		assert_eq!(errors[0].expected, vec!["type".to_string()]);
 	}
 
 	#[test]
-	fn test_return_expr() {
-		let code = r#"
-			return 1 + 5
-		"#;
+	fn test_struct_field_without_type() {
+		let errors = Parser::new("struct Todo { title }")
+			.parse()
+			.unwrap_err();
 
-		let ast = Parser::new(code)
-			.parse();
+		assert_eq!(errors[0].expected, vec!["':'".to_string()]);
+		assert_eq!(errors[0].found, Some("'}'".to_string()));
+	}
 
-		let expected = vec![
-			ASTNode::Ret(
-				Ret {
-					value: Box::new(
-						Some(
-							ASTNode::BinOp(
-								BinOp {
-									op: Op::Plus,
-									left: Box::new(ASTNode::Lit(Value::Int(1))),
-									right: Box::new(ASTNode::Lit(Value::Int(5))),
-								}
-							)
-						)
-					),
-				}
-			)
-		];
+	#[test]
+	fn test_for_missing_in() {
+		let errors = Parser::new("for x xs { }")
+			.parse()
+			.unwrap_err();
 
-		assert_eq!(ast, expected);
+		assert_eq!(errors[0].expected, vec!["'in'".to_string()]);
 	}
 
 	#[test]
-	fn test_empty_return() {
-		let code = r#"
-			return
-		"#;
+	fn test_if_missing_body() {
+		let errors = Parser::new("a = if b c")
+			.parse()
+			.unwrap_err();
 
-		let ast = Parser::new(code)
-			.parse();
+		assert_eq!(errors[0].expected, vec!["'{'".to_string()]);
+		assert_eq!(errors[0].found, Some("identifier 'c'".to_string()));
+	}
 
-		let expected = vec![
-			ASTNode::Ret(
-				Ret {
-					value: Box::new(None),
-				}
-			)
-		];
+	fn lit_values(code: &str) -> Vec<Value> {
+		Parser::new(code)
+			.parse()
+			.unwrap()
+			.into_iter()
+			.map(|node| match node.kind {
+				NodeKind::Lit(value) => value,
+				other => panic!("expected literal, got {:?}", other),
+			})
+			.collect()
+	}
 
-		assert_eq!(ast, expected);
+	#[test]
+	fn test_numeric_literals() {
+		let values = lit_values(r#"
+			1_000_000
+			0xff00ff
+			0b1010
+			1e-3
+			2.5E2
+			5.
+			.5
+			1_0.2_5
+		"#);
+
+		assert_eq!(values, vec![
+			Value::Int(1_000_000),
+			Value::Int(0xff00ff),
+			Value::Int(0b1010),
+			Value::Float(1e-3),
+			Value::Float(250.0),
+			Value::Float(5.0),
+			Value::Float(0.5),
+			Value::Float(10.25),
+		]);
 	}
 
 	#[test]
-	fn test_parse_obj_instance_in_array() {
-		let code = r#"
-			[
-				Div { }
+	fn test_unit_literals() {
+		let values = lit_values(r#"
+			50%
+			12px
+			1.5px
+			90deg
+		"#);
+
+		assert_eq!(values, vec![
+			Value::Percent(50.0),
+			Value::Px(12.0),
+			Value::Px(1.5),
+			Value::Deg(90.0),
+		]);
+
+		assert_eq!(lit_values("-45deg"), vec![Value::Deg(-45.0)]);
+	}
+
+	#[test]
+	fn test_modulo_without_spaces() {
+		let modulo = |left: NodeKind, right: i64| -> Vec<ASTNode> {
+			vec![
+				NodeKind::BinOp(
+					BinOp {
+						left: Box::new(left.into()),
+						op: Op::Modulo,
+						right: Box::new(NodeKind::Lit(Value::Int(right)).into()),
+					}
+				).into()
 			]
-		"#;
+		};
+
+		assert_eq!(Parser::new("7%3").parse().unwrap(), modulo(NodeKind::Lit(Value::Int(7)), 3));
+		assert_eq!(Parser::new("i%2").parse().unwrap(), modulo(NodeKind::Ident("i".to_string()), 2));
+	}
+
+	#[test]
+	fn test_integer_overflow_is_error() {
+		let errors = Parser::new("a = 0xffffffffffffffffff")
+			.parse()
+			.unwrap_err();
+
+		assert_eq!(errors[0].found, Some("invalid token".to_string()));
+	}
+
+	#[test]
+	fn test_string_escapes() {
+		let code = r#"a = "line\n\t\"quoted\" \$ \u{e4}""#;
 
 		let ast = Parser::new(code)
-			.parse();
+			.parse()
+			.unwrap();
 
-		let expected = vec![
-			ASTNode::Array(
-				Array {
-					items: vec![
-						ASTNode::StructIns(
-							StructIns {
-								name: "Div".to_string(),
-								probs: vec![],
-							}
-						)
-					],
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(NodeKind::Lit(Value::Str("line\n\t\"quoted\" $ \u{e4}".to_string())).into()),
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
 	}
 
 	#[test]
-	fn test_parse_vertex() {
-		let code = r#"
-			Vertex { x: -0.6, y: 0.1, color: "black" }
-		"#;
+	fn test_invalid_string_escape() {
+		let errors = Parser::new(r#"a = "bad \q""#)
+			.parse()
+			.unwrap_err();
 
-		let ast = Parser::new(code)
-			.parse();
+		assert_eq!(errors[0].found, Some("invalid token".to_string()));
+		assert_eq!(errors[0].span.start, 4);
+	}
 
-		let expected = vec![
-			ASTNode::StructIns(
-				StructIns {
-					name: "Vertex".to_string(),
-					probs: vec![
-						Property {
-							name: "x".to_string(),
-							value: Box::new(ASTNode::Lit(Value::Float(-0.6))),
-						},
-						Property {
-							name: "y".to_string(),
-							value: Box::new(ASTNode::Lit(Value::Float(0.1))),
-						},
-						Property {
-							name: "color".to_string(),
-							value: Box::new(ASTNode::Lit(Value::Str("black".to_string()))),
-						},
-					],
+	#[test]
+	fn test_string_interpolation() {
+		let mut parser = Parser::new(r#"a = "Hi ${name}, ${count + 1}""#);
+		let ast = parser.parse().unwrap();
+
+		let expected: Vec<ASTNode> = vec![
+			NodeKind::Assign(
+				Assign {
+					left: Box::new(NodeKind::Ident("a".to_string()).into()),
+					right: Box::new(
+						NodeKind::Concat(
+							Concat {
+								parts: vec![
+									NodeKind::Lit(Value::Str("Hi ".to_string())).into(),
+									NodeKind::Ident("name".to_string()).into(),
+									NodeKind::Lit(Value::Str(", ".to_string())).into(),
+									NodeKind::BinOp(
+										BinOp {
+											left: Box::new(NodeKind::Ident("count".to_string()).into()),
+											op: Op::Plus,
+											right: Box::new(NodeKind::Lit(Value::Int(1)).into()),
+										}
+									).into(),
+								],
+							}
+						).into()
+					),
 				}
-			)
+			).into()
 		];
 
 		assert_eq!(ast, expected);
+
+		// Spans of interpolated expressions point into the original input
+		let parts = match &ast[0].kind {
+			NodeKind::Assign(a) => match &a.right.kind {
+				NodeKind::Concat(c) => c.parts.clone(),
+				_ => panic!("expected concat"),
+			},
+			_ => panic!("expected assign"),
+		};
+		assert_eq!(parts[1].span, 10..14);
+
+		// Strings inside an interpolation do not end it
+		let ast = Parser::new(r#"a = "${ f("}") }""#).parse().unwrap();
+		match &ast[0].kind {
+			NodeKind::Assign(a) => match &a.right.kind {
+				NodeKind::Concat(c) => assert!(matches!(c.parts[0].kind, NodeKind::Call(_))),
+				_ => panic!("expected concat"),
+			},
+			_ => panic!("expected assign"),
+		}
+	}
+
+	#[test]
+	fn test_interpolation_errors() {
+		let code = r#"a = "x ${1 +}""#;
+		let errors = Parser::new(code).parse().unwrap_err();
+
+		assert_eq!(errors[0].found, None);
+		assert_eq!(errors[0].span.start, code.find("}\"").unwrap());
+
+		let code = r#"a = "x ${}""#;
+		let errors = Parser::new(code).parse().unwrap_err();
+
+		assert_eq!(errors[0].expected, vec!["expression".to_string()]);
+		assert_eq!(errors[0].span.start, code.find("}\"").unwrap());
+
+		let code = r#"a = "x ${1 2}""#;
+		let errors = Parser::new(code).parse().unwrap_err();
+
+		assert_eq!(errors[0].span.start, code.find("2").unwrap());
 	}
 }
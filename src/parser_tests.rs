@@ -194,4 +194,57 @@
 //             )
 //         ]);
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+	use crate::parser::*;
+
+	fn ident_names(code: &str) -> Vec<String> {
+		Parser::new(code)
+			.parse()
+			.unwrap()
+			.into_iter()
+			.map(|node| match node.kind {
+				NodeKind::Assign(assign) => match assign.left.kind {
+					NodeKind::Ident(name) => name,
+					other => panic!("expected ident, got {:?}", other),
+				},
+				other => panic!("expected assign, got {:?}", other),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_idents_with_digits() {
+		let names = ident_names(r#"
+			item2 = 1
+			player_1 = 2
+			_x9y = 3
+		"#);
+
+		assert_eq!(names, vec!["item2", "player_1", "_x9y"]);
+	}
+
+	#[test]
+	fn test_unicode_idents() {
+		let names = ident_names(r#"
+			ikkuna = "Testi Ikkuna"
+			määrä = 3
+			größe2 = 4
+			名前 = 5
+		"#);
+
+		assert_eq!(names, vec!["ikkuna", "määrä", "größe2", "名前"]);
+	}
+
+	#[test]
+	fn test_keywords_with_digits_are_idents() {
+		let names = ident_names(r#"
+			true1 = 1
+			for_2 = 2
+		"#);
+
+		assert_eq!(names, vec!["true1", "for_2"]);
+	}
+}
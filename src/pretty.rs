@@ -1,24 +1,26 @@
 use crate::parser::ASTNode;
+use crate::parser::NodeKind;
 use crate::vm::ByteCode;
+use crate::types::Value;
 
 pub fn ast_pretty_string(node: &ASTNode) -> String {
     let mut s = String::new();
 
-    match node {
-        ASTNode::StructDef(struct_def) => {
+    match &node.kind {
+        NodeKind::StructDef(struct_def) => {
             s += format!("StructDef: {}\n", struct_def.name).as_str();
             for field in struct_def.fields.iter() {
                 s += format!("    Field: {}\n", field.name).as_str();
                 // s += format!("    value: {}\n", field.typ).as_str();
             }
         },
-        ASTNode::TypeDef(type_def) => {
+        NodeKind::TypeDef(type_def) => {
             s += format!("TypeDef: {}\n", type_def.name).as_str();
             for field in type_def.fields.iter() {
                 s += format!("    Field: {}\n", field.name).as_str();
             }
         },
-        ASTNode::Fun(fn_def) => {
+        NodeKind::Fun(fn_def) => {
             let body = if fn_def.body.len() == 0 {
                 "".to_string()
             } else if fn_def.body.len() < 1 {
@@ -41,31 +43,34 @@ pub fn ast_pretty_string(node: &ASTNode) -> String {
             //     s += format!("[{}] item: {}\n", index, ast_pretty_string(statement)).as_str();
             // }
         },
-        ASTNode::Var(var) => {
+        NodeKind::Var(var) => {
             s += format!("{} {}", var.typ, var.name).as_str();
         },
-        ASTNode::Assign(assign) => {
+        NodeKind::Assign(assign) => {
             s += &format!("{} = {}", ast_pretty_string(&assign.left), ast_pretty_string(&assign.right));
             // s += "Assign:\n";
             // s += &format!("left: {}", ast_pretty_string(&assign.left));
             // s += &format!("right: {}", ast_pretty_string(&assign.right));
         },
-        ASTNode::Ident(ident) => {
+        NodeKind::Ident(ident) => {
             s += ident
         },
-        // ASTNode::Lit(lit) => {
+        // NodeKind::Lit(lit) => {
         //     s += &format!(r#""{}""#, lit);
         // },
-        // ASTNode::Int(lit) => {
+        // NodeKind::Int(lit) => {
         //     s += &lit.to_string()
         // },
-        // ASTNode::Float(lit) => {
+        // NodeKind::Float(lit) => {
         //     s += format!("LiteralDecimal: {}\n", lit).as_str();
         // },
-        ASTNode::LiteralPercent(lit) => {
-            s += format!("LiteralPercent: {}\n", lit).as_str();
+        NodeKind::Lit(Value::Str(lit)) => {
+            s += &format!(r#""{}""#, lit);
         },
-        ASTNode::StructIns(obj) => {
+        NodeKind::Lit(lit) => {
+            s += &lit.to_string();
+        },
+        NodeKind::StructIns(obj) => {
             s += &format!("{} {{\n", obj.name);
             for prob in &obj.probs {
                 s += &format!("  {}: {}\n", prob.name, ast_pretty_string(&prob.value));
@@ -74,18 +79,18 @@ pub fn ast_pretty_string(node: &ASTNode) -> String {
 
             s += format!("Object: {}\n", obj.name).as_str();
         },
-        ASTNode::Call(call) => {
+        NodeKind::Call(call) => {
             s += format!("Call: {:?}\n", call.callee).as_str();
         },
-        ASTNode::Array(array) => {
+        NodeKind::Array(array) => {
             s += &format!("[{}]", array.items.iter().map(|p| format!("{}", ast_pretty_string(p))).collect::<Vec<String>>().join(", "));
         },
-        ASTNode::Obj(obj) => {
+        NodeKind::Obj(obj) => {
             s += &format!("{{{}}}", obj.probs.iter()
                 .map(|p| format!("{}: {:?}", p.name, ast_pretty_string(&p.value)))
                 .collect::<Vec<String>>().join(", "))
         },
-        // ASTNode::Ret(ret) => {
+        // NodeKind::Ret(ret) => {
         //     match ret.value.clone() {
         //         Some(value) => {
         //             s += &format!("return {}", ast_pretty_string(&value));
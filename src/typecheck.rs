@@ -0,0 +1,1320 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use logos::Span;
+
+use crate::component::builtin_enums;
+use crate::parser::{ASTNode, Fun, NodeKind, Op, Parser, Pattern, TypeField, UnaryOp, VarType};
+use crate::types::Value;
+
+// Builtin components, declared in the language itself
+const PRELUDE: &str = include_str!("prelude.do");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    Bool,
+    Percent,
+    Px,
+    Deg,
+    // Length of a component, a plain number, pixels or a percentage
+    Size,
+    None,
+    Array(Box<Type>),
+    Struct(String),
+    Enum(String),
+    // `Fn(Int) -> Bool`, a bare `Fn` has no parameter list and accepts
+    // any function. The count is how many parameters have no default.
+    Fn(Option<Vec<Type>>, usize, Box<Type>),
+    // Type parameter of a generic builtin such as `map`
+    Param(String),
+    // Only known at runtime, never reported as a mismatch
+    Unknown,
+}
+
+impl Type {
+    fn is_number(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    fn is_unit(&self) -> bool {
+        matches!(self, Type::Percent | Type::Px | Type::Deg)
+    }
+
+    fn is_size(&self) -> bool {
+        self.is_number() || matches!(self, Type::Percent | Type::Px | Type::Size)
+    }
+
+    // Whether a value of type `other` can be stored where `self` is expected
+    fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Unknown, _) | (_, Type::Unknown) | (_, Type::None) => true,
+            (Type::Float, Type::Int) => true,
+            (Type::Size, t) => t.is_size(),
+            (Type::Array(a), Type::Array(b)) => a.accepts(b),
+            (Type::Fn(None, _, _), Type::Fn(_, _, _)) => true,
+            (Type::Fn(Some(expected), _, ret), Type::Fn(params, required, actual)) => {
+                let params = match params {
+                    Some(params) => {
+                        (*required..=params.len()).contains(&expected.len())
+                            && params.iter().zip(expected).all(|(p, e)| p.accepts(e))
+                    },
+                    None => true,
+                };
+
+                // A callback declared without a return type may return anything
+                params && (**ret == Type::None || ret.accepts(actual))
+            },
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::String => write!(f, "String"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Percent => write!(f, "Percent"),
+            Type::Px => write!(f, "Px"),
+            Type::Deg => write!(f, "Deg"),
+            Type::Size => write!(f, "Size"),
+            Type::None => write!(f, "None"),
+            Type::Array(item) => write!(f, "Array<{}>", item),
+            Type::Struct(name) | Type::Enum(name) => write!(f, "{}", name),
+            Type::Fn(None, _, _) => write!(f, "Fn"),
+            Type::Fn(Some(params), _, ret) => {
+                let params: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "Fn({})", params.join(", "))?;
+
+                match **ret {
+                    Type::None => Ok(()),
+                    _ => write!(f, " -> {}", ret),
+                }
+            },
+            Type::Param(name) => write!(f, "{}", name),
+            Type::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error at {}..{}: {}", self.span.start, self.span.end, self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    typ: Type,
+    required: bool,
+}
+
+// A variable in scope, only variables declared with a type keep the type
+// they started with
+struct Binding {
+    typ: Type,
+    annotated: bool,
+}
+
+impl Binding {
+    // `sel = none` and `x = 1` take the type of a later `sel = 5` and
+    // `x = 1.5`
+    fn widens_to(&self, typ: &Type) -> bool {
+        !self.annotated && match self.typ {
+            Type::None => *typ != Type::None,
+            Type::Int => *typ == Type::Float,
+            _ => false,
+        }
+    }
+}
+
+pub struct TypeChecker {
+    structs: HashMap<String, Vec<Field>>,
+    aliases: HashMap<String, VarType>,
+    enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    scopes: Vec<HashMap<String, Binding>>,
+    // Declared return types of the functions being checked
+    returns: Vec<Type>,
+    errors: Vec<TypeError>,
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        let enums = builtin_enums()
+            .into_iter()
+            .map(|(name, variants)| {
+                (name.to_string(), variants.iter().map(|v| (v.to_string(), vec![])).collect())
+            })
+            .collect();
+
+        let mut checker = TypeChecker {
+            structs: HashMap::new(),
+            aliases: HashMap::new(),
+            enums,
+            scopes: vec![HashMap::new()],
+            returns: vec![],
+            errors: vec![],
+        };
+
+        let prelude = Parser::new(PRELUDE).parse().expect("prelude should parse");
+        checker.declare_types(&prelude);
+
+        checker
+    }
+
+    pub fn check(mut self, ast: &[ASTNode]) -> Result<(), Vec<TypeError>> {
+        self.declare_types(ast);
+
+        for node in ast {
+            self.infer(node);
+        }
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn error(&mut self, node: &ASTNode, message: String) {
+        self.errors.push(TypeError {
+            span: node.span.clone(),
+            message,
+        });
+    }
+
+    // Names are registered before any field is resolved so definitions can
+    // refer to each other in any order.
+    fn declare_types(&mut self, ast: &[ASTNode]) {
+        for node in ast {
+            match &node.kind {
+                NodeKind::StructDef(def) => {
+                    self.structs.insert(def.name.clone(), vec![]);
+                },
+                NodeKind::TypeDef(def) => match &def.alias {
+                    Some(alias) => {
+                        self.aliases.insert(def.name.clone(), alias.clone());
+                    },
+                    None => {
+                        self.structs.insert(def.name.clone(), vec![]);
+                    },
+                },
+                NodeKind::EnumDef(def) => {
+                    self.enums.insert(def.name.clone(), vec![]);
+                },
+                _ => {}
+            }
+        }
+
+        for node in ast {
+            match &node.kind {
+                NodeKind::StructDef(def) => {
+                    let fields = self.resolve_fields(&def.fields, node);
+                    self.structs.insert(def.name.clone(), fields);
+                },
+                NodeKind::TypeDef(def) if def.alias.is_none() => {
+                    let fields = self.resolve_fields(&def.fields, node);
+                    self.structs.insert(def.name.clone(), fields);
+                },
+                NodeKind::EnumDef(def) => {
+                    let variants = def.variants
+                        .iter()
+                        .map(|v| {
+                            let payload = v.payload.iter().map(|t| self.resolve(t, node)).collect();
+                            (v.name.clone(), payload)
+                        })
+                        .collect();
+                    self.enums.insert(def.name.clone(), variants);
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn resolve_fields(&mut self, fields: &[TypeField], node: &ASTNode) -> Vec<Field> {
+        fields
+            .iter()
+            .map(|field| Field {
+                name: field.name.clone(),
+                typ: self.resolve(&field.typ, node),
+                required: field.default.is_none(),
+            })
+            .collect()
+    }
+
+    fn resolve(&mut self, typ: &VarType, node: &ASTNode) -> Type {
+        self.resolve_depth(typ, node, 0)
+    }
+
+    fn resolve_depth(&mut self, typ: &VarType, node: &ASTNode, depth: usize) -> Type {
+        match typ {
+            VarType::Int => Type::Int,
+            VarType::Float => Type::Float,
+            VarType::String | VarType::StrLit(_) => Type::String,
+            VarType::Var(name) | VarType::Ident(name) => self.resolve_name(name, node, depth),
+            VarType::Generic(name, args) => match name.as_str() {
+                "Array" if args.len() == 1 => Type::Array(Box::new(self.resolve_depth(&args[0], node, depth))),
+                "Array" => {
+                    self.error(node, format!("type 'Array' takes 1 type argument but {} were given", args.len()));
+                    Type::Unknown
+                },
+                _ => {
+                    self.error(node, format!("type '{}' takes no type arguments", name));
+                    Type::Unknown
+                },
+            },
+            VarType::Fn(params, ret) => {
+                let params: Vec<Type> = params.iter().map(|p| self.resolve_depth(p, node, depth)).collect();
+                let ret = match ret {
+                    Some(ret) => self.resolve_depth(ret, node, depth),
+                    None => Type::None,
+                };
+
+                Type::Fn(Some(params.clone()), params.len(), Box::new(ret))
+            },
+        }
+    }
+
+    fn resolve_name(&mut self, name: &str, node: &ASTNode, depth: usize) -> Type {
+        match name {
+            "Int" | "int" => return Type::Int,
+            "Float" | "float" => return Type::Float,
+            "String" | "string" => return Type::String,
+            "Bool" | "bool" => return Type::Bool,
+            "Percent" => return Type::Percent,
+            "Px" => return Type::Px,
+            "Deg" => return Type::Deg,
+            "Size" => return Type::Size,
+            "Array" => return Type::Array(Box::new(Type::Unknown)),
+            "Fn" => return Type::Fn(None, 0, Box::new(Type::Unknown)),
+            "Any" => return Type::Unknown,
+            _ => {}
+        }
+
+        if self.structs.contains_key(name) {
+            return Type::Struct(name.to_string());
+        }
+
+        if self.enums.contains_key(name) {
+            return Type::Enum(name.to_string());
+        }
+
+        if let Some(alias) = self.aliases.get(name).cloned() {
+            if depth > 16 {
+                self.error(node, format!("type alias '{}' refers to itself", name));
+                return Type::Unknown;
+            }
+
+            return self.resolve_depth(&alias, node, depth + 1);
+        }
+
+        self.error(node, format!("unknown type '{}'", name));
+        Type::Unknown
+    }
+
+    fn declare(&mut self, name: &str, typ: Type) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), Binding { typ, annotated: false });
+    }
+
+    fn declare_annotated(&mut self, name: &str, typ: Type) {
+        self.scopes.last_mut().unwrap().insert(name.to_string(), Binding { typ, annotated: true });
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).map(|b| b.typ.clone()))
+    }
+
+    fn field_type(&self, ty: &str, name: &str) -> Option<Type> {
+        self.structs.get(ty)?.iter().find(|f| f.name == name).map(|f| f.typ.clone())
+    }
+
+    fn check_block(&mut self, body: &[ASTNode]) -> Type {
+        self.scopes.push(HashMap::new());
+
+        let mut typ = Type::None;
+        for node in body {
+            typ = self.infer(node);
+        }
+
+        self.scopes.pop();
+        typ
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern, typ: Type, node: &ASTNode) {
+        match pattern {
+            Pattern::Bind(name) => self.declare(name, typ),
+            Pattern::Type(name, Some(inner)) => {
+                let typ = self.resolve_name(name, node, 0);
+                self.bind_pattern(inner, typ, node);
+            },
+            Pattern::Type(name, None) => {
+                self.resolve_name(name, node, 0);
+            },
+            Pattern::Struct(name, fields) => {
+                for (field, pattern) in fields {
+                    let typ = self.field_type(name, field).unwrap_or(Type::Unknown);
+                    self.bind_pattern(pattern, typ, node);
+                }
+            },
+            Pattern::Variant(ty, variant, payload) => {
+                let types = match self.enums.get(ty) {
+                    Some(variants) => variants.iter().find(|(name, _)| name == variant).map(|(_, types)| types.clone()),
+                    None => {
+                        self.error(node, format!("unknown type '{}'", ty));
+                        return;
+                    },
+                };
+
+                let types = match types {
+                    Some(types) => types,
+                    None => {
+                        self.error(node, format!("enum '{}' has no variant '{}'", ty, variant));
+                        return;
+                    },
+                };
+
+                if types.len() != payload.len() {
+                    self.error(node, format!(
+                        "variant '{}.{}' takes {} values but {} were given",
+                        ty, variant, types.len(), payload.len()
+                    ));
+                }
+
+                for (pattern, typ) in payload.iter().zip(types) {
+                    self.bind_pattern(pattern, typ, node);
+                }
+            },
+            Pattern::Wildcard | Pattern::Lit(_) => {}
+        }
+    }
+
+    fn infer(&mut self, node: &ASTNode) -> Type {
+        match &node.kind {
+            NodeKind::Lit(value) => value_type(value),
+            NodeKind::Ident(name) => self.lookup(name).unwrap_or(Type::Unknown),
+            NodeKind::Concat(concat) => {
+                for part in &concat.parts {
+                    self.infer(part);
+                }
+
+                Type::String
+            },
+            NodeKind::BinOp(binop) => {
+                let left = self.infer(&binop.left);
+                let right = self.infer(&binop.right);
+
+                match binop.op {
+                    Op::And | Op::Or | Op::Eq | Op::NotEq => Type::Bool,
+                    Op::Lt | Op::LtEq | Op::Gt | Op::GtEq => {
+                        let comparable = left == Type::Unknown
+                            || right == Type::Unknown
+                            || (left.is_number() && right.is_number())
+                            || left == right;
+
+                        if !comparable {
+                            self.error(node, format!("cannot compare {} and {}", left, right));
+                        }
+
+                        Type::Bool
+                    },
+                    _ => match arithmetic(&binop.op, &left, &right) {
+                        Some(typ) => typ,
+                        None => {
+                            self.error(node, format!("cannot apply '{}' to {} and {}", op_symbol(&binop.op), left, right));
+                            Type::Unknown
+                        }
+                    },
+                }
+            },
+            NodeKind::UnOp(unop) => {
+                let typ = self.infer(&unop.expr);
+
+                match unop.op {
+                    UnaryOp::Not => Type::Bool,
+                    UnaryOp::Negate => {
+                        if typ != Type::Unknown && !typ.is_size() && !typ.is_unit() {
+                            self.error(node, format!("cannot negate {}", typ));
+                            return Type::Unknown;
+                        }
+
+                        typ
+                    },
+                }
+            },
+            NodeKind::Array(array) => {
+                let mut types = vec![];
+                let mut conditional = false;
+
+                for item in &array.items {
+                    conditional |= matches!(item.kind, NodeKind::If(_) | NodeKind::ForLoop(_));
+                    types.push(self.infer(item));
+                }
+
+                if conditional {
+                    return Type::Array(Box::new(Type::Unknown));
+                }
+
+                Type::Array(Box::new(common_type(&types)))
+            },
+            NodeKind::StructIns(ins) => {
+                let fields = self.structs.get(&ins.name).cloned();
+
+                for prob in &ins.probs {
+                    let actual = self.infer(&prob.value);
+
+                    let fields = match &fields {
+                        Some(fields) => fields,
+                        None => continue,
+                    };
+
+                    match fields.iter().find(|f| f.name == prob.name) {
+                        Some(field) if !field.typ.accepts(&actual) => {
+                            self.error(&prob.value, format!(
+                                "expected {} for '{}.{}' but found {}",
+                                field.typ, ins.name, prob.name, actual
+                            ));
+                        },
+                        Some(_) => {},
+                        None => {
+                            self.error(&prob.value, format!("struct '{}' has no field '{}'", ins.name, prob.name));
+                        },
+                    }
+                }
+
+                match fields {
+                    Some(fields) => {
+                        for field in fields.iter().filter(|f| f.required) {
+                            if !ins.probs.iter().any(|p| p.name == field.name) {
+                                self.error(node, format!("missing field '{}' in '{}'", field.name, ins.name));
+                            }
+                        }
+
+                        Type::Struct(ins.name.clone())
+                    },
+                    None => Type::Unknown,
+                }
+            },
+            NodeKind::Obj(obj) => {
+                for prob in &obj.probs {
+                    self.infer(&prob.value);
+                }
+
+                Type::Unknown
+            },
+            NodeKind::VarDecl(decl) => {
+                let typ = self.infer(&decl.value);
+                self.declare(&decl.name, typ);
+                Type::None
+            },
+            NodeKind::Var(var) => {
+                let typ = self.resolve_name(&var.typ, node, 0);
+                self.declare_annotated(&var.name, typ);
+                Type::None
+            },
+            NodeKind::Assign(assign) => {
+                let typ = self.infer(&assign.right);
+
+                if let NodeKind::Ident(name) = &assign.left.kind {
+                    match self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+                        Some(var) if var.widens_to(&typ) => var.typ = typ,
+                        Some(var) if !var.typ.accepts(&typ) => {
+                            let declared = var.typ.clone();
+
+                            self.error(&assign.right, format!(
+                                "cannot assign {} to '{}' of type {}",
+                                typ, name, declared
+                            ));
+                        },
+                        Some(_) => {},
+                        None => self.declare(name, typ),
+                    }
+                } else if let NodeKind::ProbAccess(access) = &assign.left.kind {
+                    let declared = self.infer(&assign.left);
+
+                    if !declared.accepts(&typ) {
+                        self.error(&assign.right, format!(
+                            "cannot assign {} to '{}' of type {}",
+                            typ, access.property, declared
+                        ));
+                    }
+                } else {
+                    self.infer(&assign.left);
+                }
+
+                Type::None
+            },
+            NodeKind::Fun(fun) => self.infer_fun(fun, node, &[]),
+            NodeKind::Call(call) => {
+                if let Some((ty, payload)) = self.variant(&call.callee) {
+                    for (i, arg) in call.args.iter().enumerate() {
+                        let actual = self.infer(arg);
+
+                        match payload.get(i) {
+                            Some(expected) if !expected.accepts(&actual) => {
+                                self.error(arg, format!("expected {} but found {}", expected, actual));
+                            },
+                            _ => {}
+                        }
+                    }
+
+                    return Type::Enum(ty);
+                }
+
+                let callee = match &call.callee.kind {
+                    NodeKind::ProbAccess(access) => match self.infer(&access.object) {
+                        Type::Array(item) => match array_method(&access.property) {
+                            Some((params, ret)) => {
+                                let bindings = HashMap::from([("T".to_string(), *item)]);
+                                return self.check_call(&params, params.len(), &ret, bindings, &call.args, node);
+                            },
+                            None => Type::Unknown,
+                        },
+                        object => self.field_access(object, &access.property, &call.callee),
+                    },
+                    _ => self.infer(&call.callee),
+                };
+
+                match callee {
+                    Type::Fn(Some(params), required, ret) => {
+                        self.check_call(&params, required, &ret, HashMap::new(), &call.args, node)
+                    },
+                    _ => {
+                        for arg in &call.args {
+                            self.infer(arg);
+                        }
+
+                        Type::Unknown
+                    },
+                }
+            },
+            NodeKind::ProbAccess(access) => {
+                if let Some((ty, _)) = self.variant(node) {
+                    return Type::Enum(ty);
+                }
+
+                let object = self.infer(&access.object);
+                self.field_access(object, &access.property, node)
+            },
+            NodeKind::If(ifnode) => {
+                self.infer(&ifnode.cond);
+                let then = self.check_block(&ifnode.body);
+
+                match &ifnode.else_body {
+                    Some(body) => {
+                        let other = self.check_block(body);
+                        if then == other { then } else { Type::Unknown }
+                    },
+                    None => Type::Unknown,
+                }
+            },
+            NodeKind::ForLoop(for_loop) => {
+                let item = match self.infer(&for_loop.iterator) {
+                    Type::Array(item) => *item,
+                    Type::Unknown => Type::Unknown,
+                    other => {
+                        self.error(&for_loop.iterator, format!("cannot iterate over {}", other));
+                        Type::Unknown
+                    }
+                };
+
+                self.scopes.push(HashMap::new());
+                self.declare(&for_loop.item, item);
+                self.check_block(&for_loop.body);
+                self.scopes.pop();
+
+                Type::None
+            },
+            NodeKind::While(while_loop) => {
+                self.infer(&while_loop.cond);
+                self.check_block(&while_loop.body);
+                Type::None
+            },
+            NodeKind::Match(m) => {
+                let value = self.infer(&m.value);
+                let mut result: Option<Type> = None;
+
+                for arm in &m.arms {
+                    self.scopes.push(HashMap::new());
+                    self.bind_pattern(&arm.pattern, value.clone(), node);
+                    let typ = self.check_block(&arm.body);
+                    self.scopes.pop();
+
+                    result = match result {
+                        None => Some(typ),
+                        Some(prev) if prev == typ => Some(prev),
+                        Some(_) => Some(Type::Unknown),
+                    };
+                }
+
+                result.unwrap_or(Type::None)
+            },
+            NodeKind::Ret(ret) => {
+                if let Some(value) = ret.value.as_ref() {
+                    let actual = self.infer(value);
+                    let expected = self.returns.last().cloned().unwrap_or(Type::Unknown);
+
+                    if !expected.accepts(&actual) {
+                        self.error(value, format!("expected {} to be returned but found {}", expected, actual));
+                    }
+                }
+
+                Type::None
+            },
+            NodeKind::StructDef(def) => {
+                self.check_defaults(&def.name, &def.fields);
+                Type::None
+            },
+            NodeKind::TypeDef(def) => {
+                self.check_defaults(&def.name, &def.fields);
+                Type::None
+            },
+            NodeKind::Property(_, value) => {
+                self.infer(value);
+                Type::Unknown
+            },
+            NodeKind::Break | NodeKind::Continue | NodeKind::EnumDef(_) => Type::None,
+            NodeKind::Error => Type::Unknown,
+        }
+    }
+
+    // `hints` type the parameters left untyped, such as the callback given
+    // to `map`
+    fn infer_fun(&mut self, fun: &Fun, node: &ASTNode, hints: &[Type]) -> Type {
+        let declared = fun.ret.as_ref().map(|ret| self.resolve(ret, node));
+
+        self.scopes.push(HashMap::new());
+
+        let mut params = vec![];
+        for (i, param) in fun.params.iter().enumerate() {
+            let typ = match &param.typ {
+                Some(typ) => self.resolve(typ, node),
+                None => hints.get(i).cloned().unwrap_or(Type::Unknown),
+            };
+
+            if let Some(default) = &param.default {
+                let actual = self.infer(default);
+
+                if !typ.accepts(&actual) {
+                    self.error(default, format!(
+                        "expected {} for default of '{}' but found {}",
+                        typ, param.name, actual
+                    ));
+                }
+            }
+
+            match param.typ {
+                Some(_) => self.declare_annotated(&param.name, typ.clone()),
+                None => self.declare(&param.name, typ.clone()),
+            }
+            params.push(typ);
+        }
+
+        self.returns.push(declared.clone().unwrap_or(Type::Unknown));
+
+        let mut last = Type::None;
+        for item in &fun.body {
+            last = self.infer(item);
+        }
+
+        self.returns.pop();
+        self.scopes.pop();
+
+        let ret = match declared {
+            Some(ret) => {
+                if let Some(item) = fun.body.last() {
+                    if !ret.accepts(&last) {
+                        self.error(item, format!("expected {} to be returned but found {}", ret, last));
+                    }
+                }
+
+                ret
+            },
+            None => last,
+        };
+
+        // Defaults only make sense at the end, the rest are required
+        let required = fun.params.iter().rposition(|p| p.default.is_none()).map_or(0, |i| i + 1);
+
+        Type::Fn(Some(params), required, Box::new(ret))
+    }
+
+    // Checks the arguments of a call and gives the type it returns. Type
+    // parameters in `params` are bound from the arguments, left to right.
+    fn check_call(&mut self, params: &[Type], required: usize, ret: &Type, mut bindings: HashMap<String, Type>, args: &[ASTNode], node: &ASTNode) -> Type {
+        if args.len() < required || args.len() > params.len() {
+            let expected = match required == params.len() {
+                true => params.len().to_string(),
+                false => format!("{} to {}", required, params.len()),
+            };
+
+            self.error(node, format!("expected {} arguments but found {}", expected, args.len()));
+        }
+
+        for (i, arg) in args.iter().enumerate() {
+            let param = match params.get(i) {
+                Some(param) => param,
+                None => {
+                    self.infer(arg);
+                    continue;
+                }
+            };
+
+            let expected = substitute(param, &bindings);
+            let actual = match (&arg.kind, &expected) {
+                (NodeKind::Fun(fun), Type::Fn(Some(hints), _, _)) => self.infer_fun(fun, arg, hints),
+                _ => self.infer(arg),
+            };
+
+            if !unify(param, &actual, &mut bindings) {
+                self.error(arg, format!("expected {} but found {}", expected, actual));
+            }
+        }
+
+        substitute(ret, &bindings)
+    }
+
+    fn field_access(&mut self, object: Type, property: &str, node: &ASTNode) -> Type {
+        match object {
+            Type::Struct(name) => match self.field_type(&name, property) {
+                Some(typ) => typ,
+                None => {
+                    self.error(node, format!("struct '{}' has no field '{}'", name, property));
+                    Type::Unknown
+                }
+            },
+            _ => Type::Unknown,
+        }
+    }
+
+    fn check_defaults(&mut self, name: &str, fields: &[TypeField]) {
+        for field in fields {
+            let default = match &field.default {
+                Some(default) => default,
+                None => continue,
+            };
+
+            let actual = self.infer(default);
+            let expected = self.field_type(name, &field.name).unwrap_or(Type::Unknown);
+
+            if !expected.accepts(&actual) {
+                self.error(default, format!(
+                    "expected {} for default of '{}.{}' but found {}",
+                    expected, name, field.name, actual
+                ));
+            }
+        }
+    }
+
+    // `Enum.Variant` when the object names an enum and not a variable
+    fn variant(&self, node: &ASTNode) -> Option<(String, Vec<Type>)> {
+        let access = match &node.kind {
+            NodeKind::ProbAccess(access) => access,
+            _ => return None,
+        };
+
+        let name = match &access.object.kind {
+            NodeKind::Ident(name) if self.lookup(name).is_none() => name,
+            _ => return None,
+        };
+
+        let variants = self.enums.get(name)?;
+        let payload = variants
+            .iter()
+            .find(|(variant, _)| variant == &access.property)
+            .map(|(_, payload)| payload.clone())
+            .unwrap_or_default();
+
+        Some((name.clone(), payload))
+    }
+}
+
+fn value_type(value: &Value) -> Type {
+    match value {
+        Value::Int(_) => Type::Int,
+        Value::Float(_) => Type::Float,
+        Value::Str(_) => Type::String,
+        Value::Bool(_) => Type::Bool,
+        Value::Percent(_) => Type::Percent,
+        Value::Px(_) => Type::Px,
+        Value::Deg(_) => Type::Deg,
+        Value::Array(items) => {
+            let types: Vec<Type> = items.iter().map(value_type).collect();
+            Type::Array(Box::new(common_type(&types)))
+        },
+        Value::Enum { ty, .. } => Type::Enum(ty.clone()),
+        Value::Fn { .. } => Type::Fn(None, 0, Box::new(Type::Unknown)),
+        Value::Object(_) => Type::Unknown,
+        Value::None => Type::None,
+    }
+}
+
+// Element type of an array, mixed numbers widen to Float
+fn common_type(types: &[Type]) -> Type {
+    let first = match types.first() {
+        Some(first) => first,
+        None => return Type::Unknown,
+    };
+
+    if types.iter().all(|t| t == first) {
+        return first.clone();
+    }
+
+    if types.iter().all(|t| t.is_number()) {
+        return Type::Float;
+    }
+
+    Type::Unknown
+}
+
+fn arithmetic(op: &Op, left: &Type, right: &Type) -> Option<Type> {
+    match (op, left, right) {
+        (_, Type::Unknown, _) | (_, _, Type::Unknown) => Some(Type::Unknown),
+        (_, Type::Int, Type::Int) => Some(Type::Int),
+        (_, l, r) if l.is_number() && r.is_number() => Some(Type::Float),
+        (Op::Plus, Type::String, Type::String) => Some(Type::String),
+        (Op::Plus | Op::Minus, l, r) if l.is_unit() && l == r => Some(l.clone()),
+        (Op::Multiply | Op::Divide, l, r) if l.is_unit() && r.is_number() => Some(l.clone()),
+        (Op::Multiply, l, r) if l.is_number() && r.is_unit() => Some(r.clone()),
+        // Sizes may hold any length so the result is only known to be one
+        (_, l, r) if (*l == Type::Size || *r == Type::Size) && l.is_size() && r.is_size() => Some(Type::Size),
+        _ => None,
+    }
+}
+
+// Generic methods of `Array<T>` as parameter and return types
+fn array_method(name: &str) -> Option<(Vec<Type>, Type)> {
+    let t = || Type::Param("T".to_string());
+    let u = || Type::Param("U".to_string());
+
+    match name {
+        "map" => Some((
+            vec![Type::Fn(Some(vec![t()]), 1, Box::new(u()))],
+            Type::Array(Box::new(u())),
+        )),
+        "filter" => Some((
+            vec![Type::Fn(Some(vec![t()]), 1, Box::new(Type::Bool))],
+            Type::Array(Box::new(t())),
+        )),
+        _ => None,
+    }
+}
+
+// Replaces bound type parameters, unbound ones are not known yet
+fn substitute(typ: &Type, bindings: &HashMap<String, Type>) -> Type {
+    match typ {
+        Type::Param(name) => bindings.get(name).cloned().unwrap_or(Type::Unknown),
+        Type::Array(item) => Type::Array(Box::new(substitute(item, bindings))),
+        Type::Fn(params, required, ret) => Type::Fn(
+            params.as_ref().map(|params| params.iter().map(|p| substitute(p, bindings)).collect()),
+            *required,
+            Box::new(substitute(ret, bindings)),
+        ),
+        other => other.clone(),
+    }
+}
+
+// Like `accepts`, but the first use of a type parameter binds it
+fn unify(expected: &Type, actual: &Type, bindings: &mut HashMap<String, Type>) -> bool {
+    match (expected, actual) {
+        (Type::Param(name), actual) => match bindings.get(name) {
+            Some(bound) => bound.accepts(actual),
+            None => {
+                bindings.insert(name.clone(), actual.clone());
+                true
+            }
+        },
+        (Type::Array(expected), Type::Array(actual)) => unify(expected, actual, bindings),
+        (Type::Fn(Some(expected), _, ret), Type::Fn(Some(params), required, actual)) => {
+            (*required..=params.len()).contains(&expected.len())
+                && expected.iter().zip(params).all(|(e, p)| unify(e, p, bindings))
+                && (**ret == Type::None || unify(ret, actual, bindings))
+        },
+        (expected, actual) => substitute(expected, bindings).accepts(actual),
+    }
+}
+
+fn op_symbol(op: &Op) -> &'static str {
+    match op {
+        Op::Plus => "+",
+        Op::Minus => "-",
+        Op::Multiply => "*",
+        Op::Divide => "/",
+        Op::Modulo => "%",
+        Op::Eq => "==",
+        Op::NotEq => "!=",
+        Op::Lt => "<",
+        Op::LtEq => "<=",
+        Op::Gt => ">",
+        Op::GtEq => ">=",
+        Op::And => "&&",
+        Op::Or => "||",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_code(code: &str) -> Vec<String> {
+        let ast = Parser::new(code).parse().unwrap();
+
+        match TypeChecker::new().check(&ast) {
+            Ok(()) => vec![],
+            Err(errors) => errors.into_iter().map(|e| e.message).collect(),
+        }
+    }
+
+    #[test]
+    fn test_builtin_struct_field() {
+        assert_eq!(check_code(r#"Rectangle { width: "wide" }"#), vec![
+            "expected Size for 'Rectangle.width' but found String".to_string(),
+        ]);
+        assert!(check_code("Rectangle { width: 10, height: 2.5 }").is_empty());
+        assert_eq!(check_code("Rectangle { depth: 1 }"), vec![
+            "struct 'Rectangle' has no field 'depth'".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_unit_sizes() {
+        let code = r#"
+            let rect = Rectangle { width: 10px, height: 50% }
+            let wider = rect.width * 2 + 5px
+            Container { width: wider, depth: 3 }
+        "#;
+
+        assert!(check_code(code).is_empty());
+        assert_eq!(check_code("Rectangle { width: 90deg }"), vec![
+            "expected Size for 'Rectangle.width' but found Deg".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_user_struct() {
+        let code = r#"
+            struct Todo {
+                name: String
+                done: bool = false
+            }
+
+            Todo { name: "a", done: 1 }
+            Todo { done: true }
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "expected Bool for 'Todo.done' but found Int".to_string(),
+            "missing field 'name' in 'Todo'".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_field_default_and_unknown_type() {
+        let code = r#"
+            struct Point {
+                x: Float = "zero"
+                y: Distance
+            }
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "unknown type 'Distance'".to_string(),
+            "expected Float for default of 'Point.x' but found String".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_alias_and_field_access() {
+        let code = r#"
+            type Name = String
+            type Person {
+                name: Name
+            }
+
+            let p = Person { name: "a" }
+            let n = p.name - 1
+            p.age
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot apply '-' to String and Int".to_string(),
+            "struct 'Person' has no field 'age'".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_variables() {
+        let code = r#"
+            int count
+            count = "many"
+            count = 2.5
+            let a = 1
+            a = "one"
+            let b = [1, 2.5]
+            b = [1]
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot assign String to 'count' of type Int".to_string(),
+            "cannot assign Float to 'count' of type Int".to_string(),
+            "cannot assign String to 'a' of type Int".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_assign_after_none() {
+        let code = r#"
+            sel = none
+            sel = 5
+            sel = none
+            sel = "five"
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot assign String to 'sel' of type Int".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_assign_widens_int() {
+        let code = r#"
+            x = 1
+            x = 1.5
+            let y = x - "a"
+            x = 2
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot apply '-' to Float and String".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_assign_field() {
+        let code = r#"
+            let rect = Rectangle { width: 10 }
+            rect.width = 20
+            rect.height = "tall"
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot assign String to 'height' of type Size".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_operators() {
+        assert!(check_code("let a = 10px + 2px * 3").is_empty());
+        assert_eq!(check_code("let a = 10px + 2deg"), vec![
+            "cannot apply '+' to Px and Deg".to_string(),
+        ]);
+        assert_eq!(check_code(r#"let a = -"a""#), vec![
+            "cannot negate String".to_string(),
+        ]);
+        assert_eq!(check_code(r#"let a = 1 < "b""#), vec![
+            "cannot compare Int and String".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_loops_and_match() {
+        let code = r#"
+            for item in 5 {}
+            let names = ["a"]
+            for item in names {
+                let x = item * 2
+            }
+            let v = 1
+            let r = match v {
+                Int(n) => n - "x",
+                _ => 0,
+            }
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot iterate over Int".to_string(),
+            "cannot apply '*' to String and Int".to_string(),
+            "cannot apply '-' to Int and String".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_enum_payload() {
+        let code = r#"
+            enum Shape {
+                Circle(Float)
+                Square
+            }
+
+            let a = Shape.Circle("big")
+            let b = Shape.Circle(2)
+            Rectangle { width: Shape.Square }
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "expected Float but found String".to_string(),
+            "expected Size for 'Rectangle.width' but found Shape".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_payload_patterns() {
+        let code = r#"
+            enum Shape {
+                Circle(Float)
+                Square
+            }
+
+            let s = Shape.Circle(2)
+            let area = match s {
+                Shape.Circle(r) => r * r - "x",
+                Shape.Square(a) => 1,
+                Shape.Oval => 2,
+                _ => 0
+            }
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot apply '-' to Float and String".to_string(),
+            "variant 'Shape.Square' takes 0 values but 1 were given".to_string(),
+            "enum 'Shape' has no variant 'Oval'".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_typed_params() {
+        let code = r#"
+            let f = (name: String, count: Int = "one") -> Bool => {
+                let a = name - 1
+                count > 0
+            }
+            let g = (a: Int) -> String => {
+                return a
+            }
+            let h = (a: Int) -> String => a
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "expected Int for default of 'count' but found String".to_string(),
+            "cannot apply '-' to String and Int".to_string(),
+            "expected String to be returned but found Int".to_string(),
+            "expected String to be returned but found Int".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_handler_must_be_fn() {
+        assert!(check_code("Clickable { on_click: () => {} }").is_empty());
+        assert_eq!(check_code("Clickable { on_click: 1 }"), vec![
+            "expected Fn() for 'Clickable.on_click' but found Int".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_generic_fields() {
+        let code = r#"
+            struct Todo {
+                name: String
+            }
+
+            struct List {
+                todos: Array<Todo> = []
+                on_change: Fn(String) = none
+                filter: Fn(Todo) -> Bool = none
+            }
+
+            List { todos: [Todo { name: "a" }] }
+            List { todos: ["a"] }
+            List { on_change: (text) => text }
+            List { on_change: (a, b) => a }
+            List { filter: (todo) -> Int => 1 }
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "expected Array<Todo> for 'List.todos' but found Array<String>".to_string(),
+            "expected Fn(String) for 'List.on_change' but found Fn(Unknown, Unknown) -> Unknown".to_string(),
+            "expected Fn(Todo) -> Bool for 'List.filter' but found Fn(Unknown) -> Int".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_generic_map() {
+        let code = r#"
+            struct Todo {
+                name: String
+                done: Bool = false
+            }
+
+            let todos = [Todo { name: "a" }]
+            let names = todos.map((todo) => todo.name)
+            let n = names - 1
+            todos.map((todo) => todo.title)
+            let open = todos.filter((todo) => todo.name)
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "cannot apply '-' to Array<String> and Int".to_string(),
+            "struct 'Todo' has no field 'title'".to_string(),
+            "expected Fn(Todo) -> Bool but found Fn(Todo) -> String".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_call_typed_fn() {
+        let code = r#"
+            let f = (a: Int) -> Bool => a > 1
+            let b = f("x")
+            let c = f(1, 2)
+            let d = f(1) + 1
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "expected Int but found String".to_string(),
+            "expected 1 arguments but found 2".to_string(),
+            "cannot apply '+' to Bool and Int".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_call_with_defaults() {
+        let code = r#"
+            let f = (name: String, count: Int = 2) => name
+            let a = f("a")
+            let b = f("a", 3)
+            let c = f()
+            let d = f("a", 3, 4)
+            let e = f("a", "b")
+            Clickable { on_click: (event = none) => 1 }
+        "#;
+
+        assert_eq!(check_code(code), vec![
+            "expected 1 to 2 arguments but found 0".to_string(),
+            "expected 1 to 2 arguments but found 3".to_string(),
+            "expected Int but found String".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_generic_arity() {
+        assert_eq!(check_code("struct A {\n a: Array<Int, Int>\n b: Bool<String>\n}"), vec![
+            "type 'Array' takes 1 type argument but 2 were given".to_string(),
+            "type 'Bool' takes no type arguments".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_params_are_unknown() {
+        assert!(check_code("let f = (a) => { Rectangle { width: a } }").is_empty());
+    }
+}
@@ -1,3 +1,16 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+// A variable shared by the scope that declared it and the closures that
+// captured it
+pub type Upvalue = Rc<RefCell<Value>>;
+
+// Index of a struct instance in the object heap of the vm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjectRef(pub usize);
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Int(i64),
@@ -5,9 +18,124 @@ pub enum Value {
     Str(String),
     Bool(bool),
     Array(Vec<Value>),
+    // `50%`
+    Percent(f64),
+    // `12px`
+    Px(f64),
+    // `90deg`
+    Deg(f64),
+    // `FlexDir.Row`, `Shape.Circle(10px)`
+    Enum {
+        ty: String,
+        variant: String,
+        payload: Vec<Value>,
+    },
+    // A function in code block `block` taking `arity` arguments, with the
+    // variables it captured by their ids
+    Fn {
+        block: usize,
+        arity: usize,
+        upvalues: Vec<(usize, Upvalue)>,
+    },
+    // `Rectangle { width: 10px }`, the fields live in the vm
+    Object(ObjectRef),
     None,
 }
 
+impl Value {
+    // Only false and none are falsy, everything else counts as true
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Bool(false) | Value::None)
+    }
+
+    // Orders two values, ints and floats are compared numerically.
+    // Returns None when the values can not be compared.
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+            (Value::Int(l), Value::Float(r)) => (*l as f64).partial_cmp(r),
+            (Value::Float(l), Value::Int(r)) => l.partial_cmp(&(*r as f64)),
+            (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+            (Value::Str(l), Value::Str(r)) => Some(l.cmp(r)),
+            (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+            (Value::Percent(l), Value::Percent(r)) => l.partial_cmp(r),
+            (Value::Px(l), Value::Px(r)) => l.partial_cmp(r),
+            (Value::Deg(l), Value::Deg(r)) => l.partial_cmp(r),
+            (l, r) if l == r => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Int(_) => "Int",
+            Value::Float(_) => "Float",
+            Value::Str(_) => "String",
+            Value::Bool(_) => "Bool",
+            Value::Array(_) => "Array",
+            Value::Percent(_) => "Percent",
+            Value::Px(_) => "Px",
+            Value::Deg(_) => "Deg",
+            Value::Enum { ty, .. } => ty,
+            Value::Fn { .. } => "Fn",
+            Value::Object(_) => "Object",
+            Value::None => "None",
+        }
+    }
+
+    // Numbers keep their unit when negated, other values can not be negated
+    pub fn negate(&self) -> Option<Value> {
+        match self {
+            Value::Int(i) => Some(Value::Int(-i)),
+            Value::Float(f) => Some(Value::Float(-f)),
+            Value::Percent(f) => Some(Value::Percent(-f)),
+            Value::Px(f) => Some(Value::Px(-f)),
+            Value::Deg(f) => Some(Value::Deg(-f)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Array(items) => {
+                write!(f, "[")?;
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    write!(f, "{}", item)?;
+                }
+
+                write!(f, "]")
+            }
+            Value::Percent(n) => write!(f, "{}%", n),
+            Value::Px(n) => write!(f, "{}px", n),
+            Value::Deg(n) => write!(f, "{}deg", n),
+            Value::Enum { ty, variant, payload } => {
+                write!(f, "{}.{}", ty, variant)?;
+
+                if !payload.is_empty() {
+                    let payload: Vec<String> = payload.iter().map(|v| v.to_string()).collect();
+                    write!(f, "({})", payload.join(", "))?;
+                }
+
+                Ok(())
+            }
+            Value::Fn { arity, .. } => write!(f, "fn/{}", arity),
+            Value::Object(obj) => write!(f, "object#{}", obj.0),
+            Value::None => write!(f, "none"),
+        }
+    }
+}
+
 pub struct Callback<T> {
     _phantom: std::marker::PhantomData<T>,
 }
@@ -18,8 +146,9 @@ impl<T> Callback<T> {
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub enum Action {
-    Construct{ id: usize },
+    Construct{ id: usize, name: String },
     Destruct{ id: usize },
     LoadField{ id: usize, field: usize },
     StoreField{ id: usize, field: usize, val: Value },
@@ -1,11 +1,31 @@
+use crate::component::Enum;
 use crate::component::Object;
 use crate::types::Callback;
+use crate::types::Value;
 
 pub enum FlexDir {
     Col,
     Row
 }
 
+impl Enum for FlexDir {
+    fn name() -> &'static str {
+        "FlexDir"
+    }
+
+    fn variants() -> &'static [&'static str] {
+        &["Col", "Row"]
+    }
+
+    fn from_variant(variant: &str) -> Option<Self> {
+        match variant {
+            "Col" => Some(FlexDir::Col),
+            "Row" => Some(FlexDir::Row),
+            _ => None,
+        }
+    }
+}
+
 impl Default for FlexDir {
     fn default() -> Self {
         FlexDir::Col
@@ -17,6 +37,7 @@ pub enum DivChild {
     Text(Text)
 }
 
+#[derive(Default)]
 pub struct Div {
     pub dir: FlexDir,
     pub flex_grow: Option<f32>,
@@ -35,9 +56,22 @@ impl Object for Div {
     fn get_properties(&self) -> Vec<String> {
         todo!()
     }
+
+    fn set_property(&mut self, name: &str, value: &Value) {
+        match name {
+            "dir" | "flex_direction" => self.dir = FlexDir::from_value(value).unwrap_or_default(),
+            "flex_grow" => self.flex_grow = number(value),
+            "width" => self.width = number(value),
+            "height" => self.height = number(value),
+            "vscroll" => self.vscroll = value.is_truthy(),
+            "hscroll" => self.hscroll = value.is_truthy(),
+            _ => {}
+        }
+    }
 }
 
 
+#[derive(Default)]
 pub struct Text {
     pub text: String,
 }
@@ -50,6 +84,12 @@ impl Object for Text {
     fn get_properties(&self) -> Vec<String> {
         todo!()
     }
+
+    fn set_property(&mut self, name: &str, value: &Value) {
+        if name == "text" {
+            self.text = value.to_string();
+        }
+    }
 }
 
 pub struct Clickable {
@@ -59,4 +99,39 @@ pub struct Clickable {
 pub struct Keyboard {
     pub on_key_down: Callback<()>,
     pub on_key_up: Callback<()>,
+}
+
+// Sizes are given in pixels or as plain numbers
+fn number(value: &Value) -> Option<f32> {
+    match value {
+        Value::Int(i) => Some(*i as f32),
+        Value::Float(f) | Value::Px(f) => Some(*f as f32),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div_properties() {
+        let mut div = Div::default();
+
+        div.set_property("flex_direction", &Value::Enum {
+            ty: "FlexDir".to_string(),
+            variant: "Row".to_string(),
+            payload: vec![],
+        });
+        div.set_property("width", &Value::Px(120.0));
+        div.set_property("vscroll", &Value::Bool(true));
+
+        assert!(matches!(div.dir, FlexDir::Row));
+        assert_eq!(div.width, Some(120.0));
+        assert!(div.vscroll);
+
+        div.set_property("dir", &Value::Int(1));
+
+        assert!(matches!(div.dir, FlexDir::Col));
+    }
 }
\ No newline at end of file
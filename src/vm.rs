@@ -1,15 +1,23 @@
+use std::cmp::Ordering;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
+use crate::compiler::CompileRes;
 use crate::component::Object;
 use crate::parser::ASTNode;
 use crate::parser::Call;
 use crate::pretty::ast_pretty_string;
 use crate::types::Action;
 use crate::types::Const;
+use crate::types::ObjectRef;
+use crate::types::Upvalue;
 use crate::types::Value;
 
+// A field of a struct definition and its default value
 struct StructField {
-    name: String,
+    id: usize,
     value: Value,
 }
 
@@ -18,37 +26,104 @@ struct StructDef {
     fields: Vec<StructField>,
 }
 
+// A struct instance on the heap, `Value::Object` refers to it by index
+struct HeapObject {
+    name: String,
+    fields: HashMap<usize, Value>,
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum ByteCode {
     Load(usize),
-    Store,
+    Store(usize),
     CreateStruct(usize),
     AddField(usize),
-    LoadStruct(usize),
     StoreField(usize),
     InstanceStruct(usize),
+    // Sets a field of the instance being built without telling the host
+    InitField(usize),
+    // Reports every field of the built instance to the host
+    EndStruct,
     LoadConst(usize),
     MakeArray(usize),
-    MakeFn(usize),
+    // Code block and arity
+    MakeFn(usize, usize),
     Call(usize),
+    // Method name and argument count, the object is below the arguments
+    CallMethod(usize, usize),
+    Return,
+    // Adds the variable to the upvalues of the function on top of the stack
+    Capture(usize),
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Not,
+    Neg,
+    Concat(usize),
+    Pop,
+    ArrayPush(usize),
+    Jump(usize),
+    JumpIfFalse(usize),
+    IterStart,
+    IterNext(usize),
+    Define(usize),
+    PushScope,
+    PopScope,
+    Dup,
+    // Copies the value this many places below the top
+    Pick(usize),
+    TypeOf,
+    LoadField(usize),
+    MakeEnum(usize, usize),
+    // Name of the variant of the enum on top of the stack
+    VariantOf,
+    LoadPayload(usize),
 }
 
+// Variables of the running code. A function call starts a frame, code in
+// it sees the frame's own scopes and the globals but not its caller's.
 #[derive(Debug)]
 struct Scope {
-    scopes: Vec<HashMap<usize, Value>>
+    scopes: Vec<HashMap<usize, Upvalue>>,
+    // Index of the first scope of each open frame
+    frames: Vec<usize>,
 }
 
 impl Scope {
     fn new() -> Self {
         Self {
             scopes: vec![HashMap::new()],
+            frames: Vec::new(),
         }
     }
 
+    fn push_frame(&mut self) {
+        self.frames.push(self.scopes.len());
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_frame(&mut self) {
+        if let Some(start) = self.frames.pop() {
+            self.scopes.truncate(start);
+        }
+    }
+
+    // Indexes of the scopes the running code sees, innermost first and
+    // the globals last
+    fn visible(&self) -> impl Iterator<Item = usize> {
+        let start = self.frames.last().copied().unwrap_or(0);
+
+        (start..self.scopes.len()).rev().chain((start > 0).then_some(0))
+    }
+
     fn push_scope(&mut self) {
         self.scopes.push(HashMap::new());
     }
@@ -58,18 +133,35 @@ impl Scope {
     }
 
     fn insert(&mut self, id: usize, val: Value) {
+        self.bind(id, Rc::new(RefCell::new(val)));
+    }
+
+    // Makes a captured variable visible in the current scope
+    fn bind(&mut self, id: usize, cell: Upvalue) {
         if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(id, val);
+            scope.insert(id, cell);
         }
     }
 
-    fn get(&self, var: &usize) -> Option<&Value> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(val) = scope.get(var) {
-                return Some(val);
+    // Assigns to the innermost variable with the id, new variables are
+    // created in the current scope
+    fn set(&mut self, id: usize, val: Value) {
+        for i in self.visible() {
+            if let Some(var) = self.scopes[i].get(&id) {
+                *var.borrow_mut() = val;
+                return;
             }
         }
-        None
+
+        self.insert(id, val);
+    }
+
+    fn get(&self, var: &usize) -> Option<Value> {
+        self.cell(var).map(|cell| cell.borrow().clone())
+    }
+
+    fn cell(&self, var: &usize) -> Option<Upvalue> {
+        self.visible().find_map(|i| self.scopes[i].get(var).cloned())
     }
 }
 
@@ -128,10 +220,42 @@ impl CodeFile {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    // The bytecode refers to a constant that was never loaded
+    UnknownConst(usize),
+    DivisionByZero,
+    // Int arithmetic went past the range of i64
+    Overflow,
+    UndefinedVariable(String),
+    // The type of the called value
+    NotCallable(String),
+    // Type of the value and the field or method looked up on it
+    NoField(String, String),
+    // Operators and loops given values they do not work on
+    InvalidOperation(String),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::UnknownConst(id) => write!(f, "unknown constant {}", id),
+            VmError::DivisionByZero => write!(f, "division by zero"),
+            VmError::Overflow => write!(f, "integer overflow"),
+            VmError::UndefinedVariable(name) => write!(f, "undefined variable '{}'", name),
+            VmError::NotCallable(ty) => write!(f, "cannot call {}", ty),
+            VmError::NoField(ty, field) => write!(f, "{} has no field '{}'", ty, field),
+            VmError::InvalidOperation(message) => write!(f, "{}", message),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct CallItem {
     blk: usize,
     pc: usize,
+    // Stack height below the arguments, restored on return
+    base: usize,
 }
 
 pub struct Vm {
@@ -143,7 +267,14 @@ pub struct Vm {
     scope: Scope,
     stack: Vec<Value>,
     actions: Vec<Action>,
-    consts: HashMap<usize, Value>
+    consts: HashMap<usize, Value>,
+    budget: usize,
+    structs: HashMap<usize, StructDef>,
+    // Fields added since the last `CreateStruct`
+    new_fields: Vec<StructField>,
+    objects: Vec<HeapObject>,
+    // Code blocks of the builtin array methods
+    methods: HashMap<&'static str, usize>,
 }
 
 impl Vm {
@@ -158,68 +289,153 @@ impl Vm {
             stack: Vec::new(),
             actions: Vec::new(),
             consts: HashMap::new(),
+            budget: 100_000,
+            structs: HashMap::new(),
+            new_fields: Vec::new(),
+            objects: Vec::new(),
+            methods: HashMap::new(),
         }
     }
 
-    pub fn work(&mut self) -> &Vec<Action> {
-        let mut item = match self.call_stack.last_mut() {
-            Some(item) => item,
-            None => {
-                let item = CallItem {
-                    blk: 0,
-                    pc: 0,
-                };
+    // Limits how many instructions one call to `work` runs, the rest of
+    // the code continues on the next call so that long loops do not
+    // block the action loop.
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+    }
+
+    pub fn work(&mut self) -> Result<&Vec<Action>, VmError> {
+        let mut budget = self.budget;
+
+        loop {
+            let item = match self.call_stack.last_mut() {
+                Some(item) => item,
+                None => {
+                    let item = CallItem {
+                        blk: 0,
+                        pc: 0,
+                        base: 0,
+                    };
 
-                self.call_stack.push(item);
+                    self.call_stack.push(item);
 
-                self.call_stack.last_mut().unwrap()
+                    self.call_stack.last_mut().unwrap()
+                }
+            };
+
+            // Functions end with a return, only the main block runs out
+            let bytecode = &self.code_blocks[item.blk];
+            if item.pc >= bytecode.len() {
+                break;
             }
-        };
 
-        println!("item: {:?}", item);
+            if budget == 0 {
+                return Ok(&self.actions);
+            }
 
-        let bytecode = &self.code_blocks[item.blk];
+            budget -= 1;
 
-        while item.pc < bytecode.len() {
             let bc = &bytecode[item.pc];
             item.pc += 1;
 
-            println!("pc: {:04} {:?}", item.pc, bc);
-
             match bc {
                 ByteCode::Load(id) => {
-                    // let val = self.scope.get(id).unwrap().clone();
+                    match self.scope.get(id) {
+                        Some(val) => self.stack.push(val),
+                        None => return Err(VmError::UndefinedVariable(self.ident_name(*id))),
+                    }
+                }
+                ByteCode::Store(id) => {
+                    let val = self.stack.pop().unwrap();
 
-                    // self.scope.insert(*id, val);
+                    self.scope.set(*id, val);
                 }
-                ByteCode::Store => {}
                 ByteCode::CreateStruct(id) => {
-                    // let struct_def = self.objects.get(id).unwrap().clone();
+                    let def = StructDef {
+                        name: self.ident_name(*id),
+                        fields: std::mem::take(&mut self.new_fields),
+                    };
 
-                    // self.scope.insert(*id, struct_def);
+                    self.structs.insert(*id, def);
                 }
                 ByteCode::AddField(id) => {
-                    // let struct_def = self.scope.get(id).unwrap().clone();
+                    let value = self.stack.pop().unwrap();
 
-                    // self.scope.insert(*id, struct_def);
+                    self.new_fields.push(StructField { id: *id, value });
                 }
-                ByteCode::LoadStruct(id) => {
-                    // let struct_def = self.scope.get(id).unwrap().clone();
-
-                    // self.scope.insert(*id, struct_def);
-                }
-                ByteCode::StoreField(id) => {
-                    // let struct_def = self.scope.get(id).unwrap().clone();
-
-                    // self.scope.insert(*id, struct_def);
+                ByteCode::StoreField(field) => {
+                    let val = self.stack.pop().unwrap();
+                    let obj = match self.stack.last() {
+                        Some(Value::Object(obj)) => *obj,
+                        Some(other) => {
+                            return Err(VmError::NoField(other.type_name().to_string(), self.ident_name(*field)));
+                        },
+                        None => panic!("nothing to set field {} on", field),
+                    };
+
+                    self.set_field(obj, *field, val);
                 }
                 ByteCode::InstanceStruct(id) => {
-                    // let struct_def = self.scope.get(id).unwrap().clone();
-
-                    // self.scope.insert(*id, struct_def);
+                    // Structs that are not defined in code, like the
+                    // builtin components, start without fields
+                    let name = match self.structs.get(id) {
+                        Some(def) => def.name.clone(),
+                        None => self.ident_name(*id),
+                    };
+                    let obj = ObjectRef(self.objects.len());
+
+                    let fields = match self.structs.get(id) {
+                        Some(def) => def.fields.iter().map(|f| (f.id, f.value.clone())).collect(),
+                        None => HashMap::new(),
+                    };
+
+                    self.actions.push(Action::Construct { id: obj.0, name: name.clone() });
+                    self.objects.push(HeapObject { name, fields });
+
+                    self.stack.push(Value::Object(obj));
+                }
+                ByteCode::InitField(field) => {
+                    let val = self.stack.pop().unwrap();
+
+                    match self.stack.last() {
+                        Some(Value::Object(obj)) => {
+                            self.objects[obj.0].fields.insert(*field, val);
+                        },
+                        v => panic!("cannot init field {} of {:?}", field, v),
+                    }
+                }
+                ByteCode::EndStruct => {
+                    let obj = match self.stack.last() {
+                        Some(Value::Object(obj)) => *obj,
+                        v => panic!("cannot end struct {:?}", v),
+                    };
+
+                    // Ids follow the order names first appear in the code
+                    let mut fields: Vec<(usize, Value)> = self.objects[obj.0].fields
+                        .iter()
+                        .map(|(field, val)| (*field, val.clone()))
+                        .collect();
+                    fields.sort_by_key(|(field, _)| *field);
+
+                    for (field, val) in fields {
+                        self.actions.push(Action::StoreField { id: obj.0, field, val });
+                    }
+                }
+                ByteCode::LoadField(field) => {
+                    let val = match self.stack.pop().unwrap() {
+                        Value::Object(obj) => self.objects[obj.0].fields.get(field).cloned(),
+                        other => {
+                            return Err(VmError::NoField(other.type_name().to_string(), self.ident_name(*field)));
+                        },
+                    };
+
+                    self.stack.push(val.unwrap_or(Value::None));
                 }
                 ByteCode::LoadConst(id) => {
-                    let val = self.consts.get(id).unwrap().clone();
+                    let val = match self.consts.get(id) {
+                        Some(val) => val.clone(),
+                        None => return Err(VmError::UnknownConst(*id)),
+                    };
 
                     self.stack.push(val);
                 }
@@ -236,23 +452,235 @@ impl Vm {
 
                 //     // self.scope.insert(*id, struct_def);
                 // }
-                ByteCode::MakeArray(id) => {
-                    // let struct_def = self.scope.get(id).unwrap().clone();
+                ByteCode::MakeArray(n) => {
+                    let items = self.stack.split_off(self.stack.len() - n);
+
+                    self.stack.push(Value::Array(items));
+                }
+                ByteCode::MakeFn(block, arity) => {
+                    self.stack.push(Value::Fn { block: *block, arity: *arity, upvalues: Vec::new() });
+                }
+                ByteCode::Capture(id) => {
+                    let cell = match self.scope.cell(id) {
+                        Some(cell) => cell,
+                        None => return Err(VmError::UndefinedVariable(self.ident_name(*id))),
+                    };
+
+                    match self.stack.last_mut() {
+                        Some(Value::Fn { upvalues, .. }) => upvalues.push((*id, cell)),
+                        v => panic!("cannot capture into {:?}", v),
+                    }
+                }
+                ByteCode::Call(n) => self.call(*n)?,
+                ByteCode::CallMethod(name, n) => {
+                    let args = self.stack.split_off(self.stack.len() - n);
+                    let object = self.stack.pop().unwrap();
+
+                    // Methods of objects are functions in their fields,
+                    // builtin array methods get the array as the first
+                    // argument
+                    match object {
+                        Value::Object(obj) => {
+                            let method = self.objects[obj.0].fields.get(name).cloned().unwrap_or(Value::None);
+
+                            self.stack.push(method);
+                            self.stack.extend(args);
+                            self.call(*n)?;
+                        }
+                        Value::Array(_) => {
+                            let block = match self.id_to_str.get(name).and_then(|m| self.methods.get(m.as_str())) {
+                                Some(block) => *block,
+                                None => return Err(VmError::NoField("Array".to_string(), self.ident_name(*name))),
+                            };
+
+                            self.stack.push(Value::Fn { block, arity: 2, upvalues: Vec::new() });
+                            self.stack.push(object);
+                            self.stack.extend(args);
+                            self.call(n + 1)?;
+                        }
+                        other => {
+                            return Err(VmError::NoField(other.type_name().to_string(), self.ident_name(*name)));
+                        },
+                    }
+                }
+                ByteCode::Return => {
+                    let val = self.stack.pop().unwrap();
+                    let frame = self.call_stack.pop().unwrap();
+
+                    self.stack.truncate(frame.base);
+                    self.scope.pop_frame();
+                    self.stack.push(val);
+                }
+                ByteCode::Add | ByteCode::Sub | ByteCode::Mul | ByteCode::Div => {
+                    let right = self.stack.pop().unwrap();
+                    let left = self.stack.pop().unwrap();
 
-                    // self.scope.insert(*id, struct_def);
+                    self.stack.push(arithmetic(bc, left, right)?);
                 }
-                ByteCode::MakeFn(id) => {
-                    // let struct_def = self.scope.get(id).unwrap().clone();
+                ByteCode::Mod => {
+                    let right = self.stack.pop().unwrap();
+                    let left = self.stack.pop().unwrap();
+
+                    let val = match (left, right) {
+                        (Value::Int(_), Value::Int(0)) => return Err(VmError::DivisionByZero),
+                        (Value::Int(l), Value::Int(r)) => Value::Int(l.checked_rem(r).ok_or(VmError::Overflow)?),
+                        (Value::Int(l), Value::Float(r)) => Value::Float(l as f64 % r),
+                        (Value::Float(l), Value::Int(r)) => Value::Float(l % r as f64),
+                        (Value::Float(l), Value::Float(r)) => Value::Float(l % r),
+                        (l, r) => {
+                            return Err(VmError::InvalidOperation(format!(
+                                "cannot take modulo of {} and {}", l.type_name(), r.type_name()
+                            )));
+                        },
+                    };
 
-                    // self.scope.insert(*id, struct_def);
+                    self.stack.push(val);
+                }
+                ByteCode::Eq | ByteCode::NotEq | ByteCode::Lt | ByteCode::LtEq | ByteCode::Gt | ByteCode::GtEq => {
+                    let right = self.stack.pop().unwrap();
+                    let left = self.stack.pop().unwrap();
+
+                    let ord = left.compare(&right);
+
+                    let res = match bc {
+                        ByteCode::Eq => ord == Some(Ordering::Equal),
+                        ByteCode::NotEq => ord != Some(Ordering::Equal),
+                        ByteCode::Lt => ord == Some(Ordering::Less),
+                        ByteCode::LtEq => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                        ByteCode::Gt => ord == Some(Ordering::Greater),
+                        _ => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+                    };
+
+                    self.stack.push(Value::Bool(res));
                 }
-                ByteCode::Call(id) => {
-                    // let struct_def = self.scope.get(id).unwrap().clone();
+                ByteCode::Not => {
+                    let val = self.stack.pop().unwrap();
 
-                    // self.scope.insert(*id, struct_def);
+                    self.stack.push(Value::Bool(!val.is_truthy()));
+                }
+                ByteCode::Concat(n) => {
+                    let parts = self.stack.split_off(self.stack.len() - n);
+                    let s: String = parts.iter().map(|p| p.to_string()).collect();
+
+                    self.stack.push(Value::Str(s));
+                }
+                ByteCode::Pop => {
+                    self.stack.pop();
                 }
-                _ => {
-                    todo!("unhandled bytecode: {:?}", bc);
+                ByteCode::ArrayPush(depth) => {
+                    let val = self.stack.pop().unwrap();
+                    let at = self.stack.len() - 1 - depth;
+
+                    match &mut self.stack[at] {
+                        Value::Array(items) => items.push(val),
+                        v => panic!("cannot push to {:?}", v),
+                    }
+                }
+                ByteCode::IterStart => {
+                    match self.stack.last() {
+                        Some(Value::Array(_)) => self.stack.push(Value::Int(0)),
+                        Some(v) => {
+                            return Err(VmError::InvalidOperation(format!("cannot iterate over {}", v.type_name())));
+                        },
+                        None => panic!("nothing to iterate over"),
+                    }
+                }
+                ByteCode::IterNext(target) => {
+                    let len = self.stack.len();
+
+                    let next = match (&self.stack[len - 2], &self.stack[len - 1]) {
+                        (Value::Array(items), Value::Int(i)) => items.get(*i as usize).cloned(),
+                        v => panic!("invalid iterator {:?}", v),
+                    };
+
+                    match next {
+                        Some(val) => {
+                            if let Value::Int(i) = &mut self.stack[len - 1] {
+                                *i += 1;
+                            }
+
+                            self.stack.push(val);
+                        }
+                        None => {
+                            self.stack.truncate(len - 2);
+                            item.pc = *target;
+                        }
+                    }
+                }
+                ByteCode::Define(id) => {
+                    let val = self.stack.pop().unwrap();
+
+                    self.scope.insert(*id, val);
+                }
+                ByteCode::Dup => {
+                    let val = self.stack.last().unwrap().clone();
+
+                    self.stack.push(val);
+                }
+                ByteCode::Pick(depth) => {
+                    let val = self.stack[self.stack.len() - 1 - depth].clone();
+
+                    self.stack.push(val);
+                }
+                ByteCode::TypeOf => {
+                    let name = match self.stack.pop().unwrap() {
+                        Value::Object(obj) => self.objects[obj.0].name.clone(),
+                        val => val.type_name().to_string(),
+                    };
+
+                    self.stack.push(Value::Str(name));
+                }
+                ByteCode::VariantOf => {
+                    let val = match self.stack.pop().unwrap() {
+                        Value::Enum { variant, .. } => Value::Str(variant),
+                        _ => Value::None,
+                    };
+
+                    self.stack.push(val);
+                }
+                ByteCode::LoadPayload(i) => {
+                    let val = match self.stack.pop().unwrap() {
+                        Value::Enum { mut payload, .. } if *i < payload.len() => payload.swap_remove(*i),
+                        _ => Value::None,
+                    };
+
+                    self.stack.push(val);
+                }
+                ByteCode::MakeEnum(id, n) => {
+                    let values = self.stack.split_off(self.stack.len() - n);
+                    let mut val = match self.consts.get(id) {
+                        Some(val) => val.clone(),
+                        None => return Err(VmError::UnknownConst(*id)),
+                    };
+
+                    if let Value::Enum { payload, .. } = &mut val {
+                        *payload = values;
+                    }
+
+                    self.stack.push(val);
+                }
+                ByteCode::PushScope => self.scope.push_scope(),
+                ByteCode::PopScope => self.scope.pop_scope(),
+                ByteCode::Jump(target) => {
+                    item.pc = *target;
+                }
+                ByteCode::JumpIfFalse(target) => {
+                    if !self.stack.pop().unwrap().is_truthy() {
+                        item.pc = *target;
+                    }
+                }
+                ByteCode::Neg => {
+                    let val = match self.stack.pop().unwrap() {
+                        Value::Int(i) => Value::Int(i.checked_neg().ok_or(VmError::Overflow)?),
+                        val => match val.negate() {
+                            Some(v) => v,
+                            None => {
+                                return Err(VmError::InvalidOperation(format!("cannot negate {}", val.type_name())));
+                            },
+                        },
+                    };
+
+                    self.stack.push(val);
                 }
             }
         }
@@ -263,7 +691,7 @@ impl Vm {
             self.actions.push(Action::Quit);
         }
 
-        &self.actions
+        Ok(&self.actions)
     }
 
     pub fn clear_actions(&mut self) {
@@ -274,6 +702,32 @@ impl Vm {
         self.consts.insert(c.id, c.value);
     }
 
+    // Loads a compiled program into a fresh vm and returns the id of its
+    // main code block, its functions follow it
+    pub fn load(&mut self, res: &CompileRes) -> usize {
+        for c in &res.consts {
+            self.store_const(c.clone());
+        }
+
+        for (name, id) in &res.idents {
+            self.str_to_id.insert(name.clone(), *id);
+            self.id_to_str.insert(*id, name.clone());
+        }
+
+        let id = self.create_code_block(&res.bytecode);
+
+        for f in &res.functions {
+            self.create_code_block(f);
+        }
+
+        for (name, code) in array_methods() {
+            let block = self.create_code_block(&code);
+            self.methods.insert(name, block);
+        }
+
+        id
+    }
+
     pub fn create_code_block(&mut self, code: &[ByteCode]) -> usize {
         let id = self.code_blocks.len();
 
@@ -298,6 +752,42 @@ impl Vm {
     //     self
     // }
 
+    fn call(&mut self, n: usize) -> Result<(), VmError> {
+        let mut args = self.stack.split_off(self.stack.len() - n);
+
+        let (block, arity, upvalues) = match self.stack.pop() {
+            Some(Value::Fn { block, arity, upvalues }) => (block, arity, upvalues),
+            Some(v) => return Err(VmError::NotCallable(v.type_name().to_string())),
+            None => panic!("nothing to call"),
+        };
+
+        // Left out arguments are none, extra ones are dropped
+        args.resize(arity, Value::None);
+
+        let base = self.stack.len();
+        self.stack.extend(args);
+        self.scope.push_frame();
+        for (id, cell) in upvalues {
+            self.scope.bind(id, cell);
+        }
+        self.call_stack.push(CallItem { blk: block, pc: 0, base });
+
+        Ok(())
+    }
+
+    fn ident_name(&self, id: usize) -> String {
+        match self.id_to_str.get(&id) {
+            Some(name) => name.clone(),
+            None => id.to_string(),
+        }
+    }
+
+    // Sets a field of a heap object and tells the host about it
+    fn set_field(&mut self, obj: ObjectRef, field: usize, val: Value) {
+        self.objects[obj.0].fields.insert(field, val.clone());
+        self.actions.push(Action::StoreField { id: obj.0, field, val });
+    }
+
     fn get_str(&mut self, ident: &str) -> usize {
         match self.str_to_id.get(ident) {
             Some(id) => *id,
@@ -441,9 +931,884 @@ impl Vm {
 }
 
 
+// `map` and `filter` called with the array and the callback. The result
+// is built while the array is iterated, the callback is picked from below
+// the iterator for each item.
+fn array_methods() -> Vec<(&'static str, Vec<ByteCode>)> {
+    let map = vec![
+        ByteCode::MakeArray(0),
+        ByteCode::Pick(2),
+        ByteCode::IterStart,
+        ByteCode::IterNext(10),
+        ByteCode::Pick(4),
+        ByteCode::Pick(1),
+        ByteCode::Call(1),
+        ByteCode::ArrayPush(3),
+        ByteCode::Pop,
+        ByteCode::Jump(3),
+        ByteCode::Return,
+    ];
+
+    let filter = vec![
+        ByteCode::MakeArray(0),
+        ByteCode::Pick(2),
+        ByteCode::IterStart,
+        ByteCode::IterNext(12),
+        ByteCode::Pick(4),
+        ByteCode::Pick(1),
+        ByteCode::Call(1),
+        ByteCode::JumpIfFalse(10),
+        ByteCode::ArrayPush(2),
+        ByteCode::Jump(3),
+        ByteCode::Pop,
+        ByteCode::Jump(3),
+        ByteCode::Return,
+    ];
+
+    vec![("map", map), ("filter", filter)]
+}
+
+// Ints stay ints, mixing them with floats gives a float. Units can be
+// added to the same unit and scaled by plain numbers.
+fn arithmetic(bc: &ByteCode, left: Value, right: Value) -> Result<Value, VmError> {
+    fn apply(bc: &ByteCode, l: f64, r: f64) -> f64 {
+        match bc {
+            ByteCode::Add => l + r,
+            ByteCode::Sub => l - r,
+            ByteCode::Mul => l * r,
+            _ => l / r,
+        }
+    }
+
+    let val = match (bc, left, right) {
+        (ByteCode::Div, Value::Int(_), Value::Int(0)) => return Err(VmError::DivisionByZero),
+        (_, Value::Int(l), Value::Int(r)) => {
+            let val = match bc {
+                ByteCode::Add => l.checked_add(r),
+                ByteCode::Sub => l.checked_sub(r),
+                ByteCode::Mul => l.checked_mul(r),
+                _ => l.checked_div(r),
+            };
+
+            Value::Int(val.ok_or(VmError::Overflow)?)
+        },
+        (_, Value::Int(l), Value::Float(r)) => Value::Float(apply(bc, l as f64, r)),
+        (_, Value::Float(l), Value::Int(r)) => Value::Float(apply(bc, l, r as f64)),
+        (_, Value::Float(l), Value::Float(r)) => Value::Float(apply(bc, l, r)),
+        (ByteCode::Add, Value::Str(l), Value::Str(r)) => Value::Str(l + &r),
+        (ByteCode::Add | ByteCode::Sub, Value::Px(l), Value::Px(r)) => Value::Px(apply(bc, l, r)),
+        (ByteCode::Add | ByteCode::Sub, Value::Percent(l), Value::Percent(r)) => Value::Percent(apply(bc, l, r)),
+        (ByteCode::Add | ByteCode::Sub, Value::Deg(l), Value::Deg(r)) => Value::Deg(apply(bc, l, r)),
+        (ByteCode::Mul | ByteCode::Div, unit, Value::Int(r)) if unit.negate().is_some() => scale(bc, unit, r as f64),
+        (ByteCode::Mul | ByteCode::Div, unit, Value::Float(r)) if unit.negate().is_some() => scale(bc, unit, r),
+        (ByteCode::Mul, Value::Int(l), unit) if unit.negate().is_some() => scale(bc, unit, l as f64),
+        (ByteCode::Mul, Value::Float(l), unit) if unit.negate().is_some() => scale(bc, unit, l),
+        (bc, l, r) => {
+            let op = match bc {
+                ByteCode::Add => "+",
+                ByteCode::Sub => "-",
+                ByteCode::Mul => "*",
+                _ => "/",
+            };
+
+            return Err(VmError::InvalidOperation(format!(
+                "cannot apply '{}' to {} and {}", op, l.type_name(), r.type_name()
+            )));
+        },
+    };
+
+    Ok(val)
+}
+
+fn scale(bc: &ByteCode, unit: Value, by: f64) -> Value {
+    let by = match bc {
+        ByteCode::Div => 1.0 / by,
+        _ => by,
+    };
+
+    match unit {
+        Value::Px(n) => Value::Px(n * by),
+        Value::Percent(n) => Value::Percent(n * by),
+        Value::Deg(n) => Value::Deg(n * by),
+        v => panic!("cannot scale {:?}", v),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::typecheck::TypeChecker;
+
+    #[test]
+    fn test_load_bool_and_none_consts() {
+        let mut vm = Vm::new();
+
+        vm.store_const(Const { id: 0, value: Value::Bool(true) });
+        vm.store_const(Const { id: 1, value: Value::None });
+
+        vm.create_code_block(&[
+            ByteCode::LoadConst(0),
+            ByteCode::LoadConst(1),
+            ByteCode::LoadConst(0),
+            ByteCode::Not,
+        ]);
+
+        vm.work().unwrap();
+
+        assert_eq!(vm.stack, vec![
+            Value::Bool(true),
+            Value::None,
+            Value::Bool(false),
+        ]);
+    }
+
+    #[test]
+    fn test_jumps_and_array_push() {
+        let mut vm = Vm::new();
+
+        vm.store_const(Const { id: 0, value: Value::Bool(false) });
+        vm.store_const(Const { id: 1, value: Value::Int(1) });
+        vm.store_const(Const { id: 2, value: Value::Int(2) });
+
+        // [1, if false { 2 } else { 1 }]
+        vm.create_code_block(&[
+            ByteCode::MakeArray(0),
+            ByteCode::LoadConst(1),
+            ByteCode::ArrayPush(0),
+            ByteCode::LoadConst(0),
+            ByteCode::JumpIfFalse(8),
+            ByteCode::LoadConst(2),
+            ByteCode::ArrayPush(0),
+            ByteCode::Jump(10),
+            ByteCode::LoadConst(1),
+            ByteCode::ArrayPush(0),
+        ]);
+
+        vm.work().unwrap();
+
+        assert_eq!(vm.stack, vec![
+            Value::Array(vec![Value::Int(1), Value::Int(1)]),
+        ]);
+    }
+
+    #[test]
+    fn test_for_comprehension() {
+        let mut vm = Vm::new();
+
+        vm.store_const(Const { id: 0, value: Value::Array(vec![Value::Int(1), Value::Int(2)]) });
+
+        // [for x in xs { for y in xs { x y } }]
+        vm.create_code_block(&[
+            ByteCode::MakeArray(0),
+            ByteCode::LoadConst(0),
+            ByteCode::IterStart,
+            ByteCode::IterNext(19),
+            ByteCode::PushScope,
+            ByteCode::Define(0),
+            ByteCode::LoadConst(0),
+            ByteCode::IterStart,
+            ByteCode::IterNext(17),
+            ByteCode::PushScope,
+            ByteCode::Define(1),
+            ByteCode::Load(0),
+            ByteCode::ArrayPush(4),
+            ByteCode::Load(1),
+            ByteCode::ArrayPush(4),
+            ByteCode::PopScope,
+            ByteCode::Jump(8),
+            ByteCode::PopScope,
+            ByteCode::Jump(3),
+        ]);
+
+        vm.work().unwrap();
+
+        assert_eq!(vm.stack, vec![
+            Value::Array(vec![
+                Value::Int(1), Value::Int(1),
+                Value::Int(1), Value::Int(2),
+                Value::Int(2), Value::Int(1),
+                Value::Int(2), Value::Int(2),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn test_budget_pauses_work() {
+        let mut vm = Vm::new();
+        vm.set_budget(2);
+
+        vm.store_const(Const { id: 0, value: Value::Int(1) });
+
+        vm.create_code_block(&[
+            ByteCode::LoadConst(0),
+            ByteCode::LoadConst(0),
+            ByteCode::LoadConst(0),
+        ]);
+
+        assert!(vm.work().unwrap().is_empty());
+        assert_eq!(vm.stack.len(), 2);
+
+        assert!(matches!(vm.work().unwrap().last(), Some(Action::Quit)));
+        assert_eq!(vm.stack.len(), 3);
+    }
+
+    #[test]
+    fn test_endless_loop_returns() {
+        let mut vm = Vm::new();
+        vm.set_budget(1000);
+
+        vm.create_code_block(&[
+            ByteCode::Jump(0),
+        ]);
+
+        assert!(vm.work().unwrap().is_empty());
+        assert!(vm.work().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let mut vm = Vm::new();
+
+        vm.store_const(Const { id: 0, value: Value::Int(1) });
+        vm.store_const(Const { id: 1, value: Value::Int(2) });
+
+        // let a = 1 let b = 1 { let a = 2 b = 2 } a b
+        vm.create_code_block(&[
+            ByteCode::LoadConst(0),
+            ByteCode::Define(0),
+            ByteCode::LoadConst(0),
+            ByteCode::Define(1),
+            ByteCode::PushScope,
+            ByteCode::LoadConst(1),
+            ByteCode::Define(0),
+            ByteCode::LoadConst(1),
+            ByteCode::Store(1),
+            ByteCode::PopScope,
+            ByteCode::Load(0),
+            ByteCode::Load(1),
+        ]);
+
+        vm.work().unwrap();
+
+        assert_eq!(vm.stack, vec![
+            Value::Int(1),
+            Value::Int(2),
+        ]);
+    }
+
+    // Runs the code and returns the vm with the ids of its variables
+    fn run_vm(code: &str) -> (Vm, HashMap<String, usize>) {
+        let ast = Parser::new(code).parse().unwrap();
+        let res = Compiler::new().compile(ast).unwrap();
+
+        let mut vm = Vm::new();
+        vm.load(&res);
+        vm.work().unwrap();
+
+        (vm, res.idents)
+    }
+
+    fn run_code(code: &str) -> Vec<Value> {
+        run_vm(code).0.stack
+    }
+
+    fn var(vm: &Vm, idents: &HashMap<String, usize>, name: &str) -> Option<Value> {
+        vm.scope.get(&idents[name])
+    }
+
+    #[test]
+    fn test_unknown_const() {
+        let mut vm = Vm::new();
+
+        vm.create_code_block(&[
+            ByteCode::LoadConst(3),
+        ]);
+
+        assert!(matches!(vm.work(), Err(VmError::UnknownConst(3))));
+        assert_eq!(VmError::UnknownConst(3).to_string(), "unknown constant 3");
+    }
+
+    #[test]
+    fn test_load_program() {
+        let ast = Parser::new("a = 2\nb = a * 2").parse().unwrap();
+        let res = Compiler::new().compile(ast).unwrap();
+
+        let mut vm = Vm::new();
+        assert_eq!(vm.load(&res), 0);
+        vm.work().unwrap();
+
+        assert_eq!(vm.scope.get(&res.idents["b"]), Some(Value::Int(4)));
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let code = r#"
+            add = (a, b) => a + b
+            children = () => {
+                return [add(1, 2), add(3, 4)]
+            }
+            children()
+        "#;
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![Value::Int(3), Value::Int(7)]),
+        ]);
+    }
+
+    #[test]
+    fn test_default_and_missing_args() {
+        let code = r#"
+            f = (a, b: Int = 10) => [a, b]
+            [f(1), f(1, 2), f()]
+        "#;
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![
+                Value::Array(vec![Value::Int(1), Value::Int(10)]),
+                Value::Array(vec![Value::Int(1), Value::Int(2)]),
+                Value::Array(vec![Value::None, Value::Int(10)]),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let code = r#"
+            fact = (n) => {
+                if n <= 1 {
+                    return 1
+                }
+                n * fact(n - 1)
+            }
+            fact(10)
+        "#;
+
+        assert_eq!(run_code(code), vec![Value::Int(3628800)]);
+    }
+
+    #[test]
+    fn test_return_from_loop() {
+        let code = r#"
+            let xs = [1, 5, 8]
+            find = (min) => {
+                for x in xs {
+                    if x > min {
+                        return x
+                    }
+                }
+                return none
+            }
+            [find(2), find(10)]
+        "#;
+
+        let (vm, _) = run_vm(code);
+
+        assert_eq!(vm.stack, vec![
+            Value::Array(vec![Value::Int(5), Value::None]),
+        ]);
+        assert!(vm.call_stack.is_empty());
+        assert!(vm.scope.frames.is_empty());
+    }
+
+    #[test]
+    fn test_locals_stay_in_frame() {
+        let code = r#"
+            let a = 1
+            f = () => {
+                let a = 2
+                b = 3
+                a
+            }
+            f()
+        "#;
+
+        let (vm, idents) = run_vm(code);
+
+        assert_eq!(vm.stack, vec![Value::Int(2)]);
+        assert_eq!(var(&vm, &idents, "a"), Some(Value::Int(1)));
+        assert_eq!(var(&vm, &idents, "b"), None);
+    }
+
+    #[test]
+    fn test_closure_outlives_call() {
+        let code = r#"
+            counter = () => {
+                count = 0
+                return () => {
+                    count = count + 1
+                    count
+                }
+            }
+            let next = counter()
+            let other = counter()
+            [next(), next(), other(), next()]
+        "#;
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![Value::Int(1), Value::Int(2), Value::Int(1), Value::Int(3)]),
+        ]);
+    }
+
+    #[test]
+    fn test_closure_writes_enclosing_variable() {
+        // Like the `on_click` handler of the todo example
+        let code = r#"
+            children = () => {
+                new_todo_name = "draft"
+                let before = [new_todo_name]
+                on_click = () => {
+                    new_todo_name = ""
+                }
+                on_click()
+                [before, new_todo_name]
+            }
+            children()
+        "#;
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![
+                Value::Array(vec![Value::Str("draft".to_string())]),
+                Value::Str("".to_string()),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn test_closures_share_variable() {
+        let code = r#"
+            make = () => {
+                name = "a"
+                set = (v) => {
+                    name = v
+                }
+                get = () => name
+                [set, get]
+            }
+            let fns = make()
+            let get = none
+            for f in fns {
+                if get == none {
+                    f("b")
+                }
+                get = f
+            }
+            get()
+        "#;
+
+        assert_eq!(run_code(code), vec![Value::Str("b".to_string())]);
+    }
+
+    #[test]
+    fn test_capture_through_nested_functions() {
+        let code = r#"
+            outer = (a) => {
+                (b) => {
+                    (c) => a + b + c
+                }
+            }
+            outer(1)(10)(100)
+        "#;
+
+        assert_eq!(run_code(code), vec![Value::Int(111)]);
+    }
+
+    #[test]
+    fn test_recursive_local_function() {
+        let code = r#"
+            outer = () => {
+                fact = (n) => {
+                    if n < 2 {
+                        return 1
+                    }
+                    return n * fact(n - 1)
+                }
+                return fact(5)
+            }
+            outer()
+        "#;
+
+        assert_eq!(run_code(code), vec![Value::Int(120)]);
+    }
+
+    #[test]
+    fn test_loop_closures_capture_each_item() {
+        let code = r#"
+            let xs = [1, 2, 3]
+            let fns = [for x in xs { () => x * 10 }]
+            [for f in fns { f() }]
+        "#;
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![Value::Int(10), Value::Int(20), Value::Int(30)]),
+        ]);
+    }
+
+    #[test]
+    fn test_int_arithmetic() {
+        assert_eq!(run_code("1 + 2 * 3"), vec![Value::Int(7)]);
+        assert_eq!(run_code("(1 + 2) * 3 - 4"), vec![Value::Int(5)]);
+        assert_eq!(run_code("7 / 2"), vec![Value::Int(3)]);
+        assert_eq!(run_code("-7 % 3"), vec![Value::Int(-1)]);
+    }
+
+    #[test]
+    fn test_float_promotion() {
+        assert_eq!(run_code("7 / 2.0"), vec![Value::Float(3.5)]);
+        assert_eq!(run_code("1 + 0.5"), vec![Value::Float(1.5)]);
+        assert_eq!(run_code("0.5 * 4"), vec![Value::Float(2.0)]);
+        assert_eq!(run_code("2.5 - 0.5"), vec![Value::Float(2.0)]);
+    }
+
+    #[test]
+    fn test_unit_and_string_arithmetic() {
+        assert_eq!(run_code("10px + 5px"), vec![Value::Px(15.0)]);
+        assert_eq!(run_code("2 * 10%"), vec![Value::Percent(20.0)]);
+        assert_eq!(run_code("90deg / 2"), vec![Value::Deg(45.0)]);
+        assert_eq!(run_code(r#""foo" + "bar""#), vec![Value::Str("foobar".to_string())]);
+    }
+
+    fn run_err(code: &str) -> VmError {
+        let ast = Parser::new(code).parse().unwrap();
+        let res = Compiler::new().compile(ast).unwrap();
+
+        let mut vm = Vm::new();
+        vm.load(&res);
+
+        match vm.work() {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        assert_eq!(run_err("1 / 0"), VmError::DivisionByZero);
+        assert_eq!(run_err("5 % 0"), VmError::DivisionByZero);
+        assert_eq!(run_err("1 / 0").to_string(), "division by zero");
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(run_err("9223372036854775807 + 1"), VmError::Overflow);
+        assert_eq!(run_err("a = -9223372036854775807 - 1\na * -1"), VmError::Overflow);
+        assert_eq!(run_err("a = -9223372036854775807 - 1\nb = -a"), VmError::Overflow);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(run_err(r#""a" - 1"#).to_string(), "cannot apply '-' to String and Int");
+        assert_eq!(run_err(r#"2 * "a""#).to_string(), "cannot apply '*' to Int and String");
+        assert_eq!(run_err("2.5 * none").to_string(), "cannot apply '*' to Float and None");
+        assert_eq!(run_err("missing + 1").to_string(), "undefined variable 'missing'");
+        assert_eq!(run_err("a = 1\na()").to_string(), "cannot call Int");
+        assert_eq!(run_err("a = 1\na.width").to_string(), "Int has no field 'width'");
+        assert_eq!(run_err("a = 1\na.width = 2").to_string(), "Int has no field 'width'");
+        assert_eq!(run_err("a = [1]\na.sort()").to_string(), "Array has no field 'sort'");
+        assert_eq!(run_err("for x in 5 { x }").to_string(), "cannot iterate over Int");
+    }
+
+    #[test]
+    fn test_variables() {
+        let (vm, idents) = run_vm("a = 1\nb = a + 2\na = b * 2");
+
+        assert!(vm.stack.is_empty());
+        assert_eq!(var(&vm, &idents, "a"), Some(Value::Int(6)));
+        assert_eq!(var(&vm, &idents, "b"), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn test_array_from_stack() {
+        let (vm, _) = run_vm("a = 2\n[1, a, a * 1.5]");
+
+        assert_eq!(vm.stack, vec![
+            Value::Array(vec![Value::Int(1), Value::Int(2), Value::Float(3.0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_while_sum() {
+        let code = r#"
+            let i = 0
+            let sum = 0
+            while i < 5 {
+                sum = sum + i
+                i = i + 1
+            }
+        "#;
+
+        let (vm, idents) = run_vm(code);
+
+        assert!(vm.stack.is_empty());
+        assert_eq!(var(&vm, &idents, "i"), Some(Value::Int(5)));
+        assert_eq!(var(&vm, &idents, "sum"), Some(Value::Int(10)));
+    }
+
+    #[test]
+    fn test_for_sum() {
+        let code = r#"
+            let xs = [1, 2.5, 3]
+            let total = 0.0
+            for x in xs {
+                total = total + x
+            }
+            total
+        "#;
+
+        assert_eq!(run_code(code), vec![Value::Float(6.5)]);
+    }
+
+    #[test]
+    fn test_for_over_array_literal() {
+        let code = r#"
+            let total = 0
+            for x in [1, 2, 3] {
+                total = total + x
+            }
+            total
+        "#;
+
+        assert_eq!(run_code(code), vec![Value::Int(6)]);
+    }
+
+    #[test]
+    fn test_logic_short_circuits() {
+        // The right sides would fail on the undefined variable
+        let code = r#"
+            [true || missing, false && missing, none || 0, 1 && none]
+        "#;
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Bool(true),
+                Value::Bool(false),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn test_match() {
+        let code = r#"
+            match value {
+                Int(n) => n,
+                "foo" => "yes",
+                Float => "float",
+                _ => "no"
+            }
+        "#;
+
+        assert_eq!(run_code(&code.replace("value", "5")), vec![Value::Int(5)]);
+        assert_eq!(run_code(&code.replace("value", r#""foo""#)), vec![Value::Str("yes".to_string())]);
+        assert_eq!(run_code(&code.replace("value", "2.5")), vec![Value::Str("float".to_string())]);
+        assert_eq!(run_code(&code.replace("value", "true")), vec![Value::Str("no".to_string())]);
+        assert_eq!(run_code("match 1 { 2 => 3 }"), vec![Value::None]);
+    }
+
+    #[test]
+    fn test_enum_values() {
+        let stack = run_code(r#"
+            enum Shape { Circle(Float), Empty }
+            [Shape.Circle(2.5), Shape::Empty]
+        "#);
+
+        assert_eq!(stack, vec![
+            Value::Array(vec![
+                Value::Enum {
+                    ty: "Shape".to_string(),
+                    variant: "Circle".to_string(),
+                    payload: vec![Value::Float(2.5)],
+                },
+                Value::Enum {
+                    ty: "Shape".to_string(),
+                    variant: "Empty".to_string(),
+                    payload: vec![],
+                },
+            ]),
+        ]);
+        assert_eq!(stack[0].to_string(), "[Shape.Circle(2.5), Shape.Empty]");
+    }
+
+    #[test]
+    fn test_payload_patterns() {
+        let code = r#"
+            enum Shape { Circle(Float), Rect(Float, Float), Empty }
+            area = (shape) => match shape {
+                Shape.Circle(r) => 3 * r * r,
+                Shape::Rect(w, h) => w * h,
+                FlexDir.Row => -1,
+                _ => 0
+            }
+            [area(Shape.Circle(2.0)), area(Shape.Rect(2.0, 1.5)), area(Shape.Empty), area(FlexDir.Row), area(5)]
+        "#;
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![
+                Value::Float(12.0),
+                Value::Float(3.0),
+                Value::Int(0),
+                Value::Int(-1),
+                Value::Int(0),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn test_concat() {
+        let mut vm = Vm::new();
+
+        vm.store_const(Const { id: 0, value: Value::Str("n = ".to_string()) });
+        vm.store_const(Const { id: 1, value: Value::Int(5) });
+        vm.store_const(Const { id: 2, value: Value::Bool(true) });
+
+        vm.create_code_block(&[
+            ByteCode::LoadConst(0),
+            ByteCode::LoadConst(1),
+            ByteCode::LoadConst(2),
+            ByteCode::Concat(3),
+        ]);
+
+        vm.work().unwrap();
+
+        assert_eq!(vm.stack, vec![
+            Value::Str("n = 5true".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_struct_instance() {
+        let code = r#"
+            struct Todo {
+                name: String
+                done: Bool = false
+            }
+            todo = Todo { name: "milk" }
+            let state = match todo {
+                Todo { done: false } => "open",
+                _ => "done"
+            }
+            [todo.name, todo.done, state]
+        "#;
+
+        let (vm, idents) = run_vm(code);
+
+        assert_eq!(vm.stack, vec![
+            Value::Array(vec![
+                Value::Str("milk".to_string()),
+                Value::Bool(false),
+                Value::Str("open".to_string()),
+            ]),
+        ]);
+        assert_eq!(var(&vm, &idents, "todo"), Some(Value::Object(ObjectRef(0))));
+        assert_eq!(vm.objects.len(), 1);
+        assert_eq!(vm.objects[0].name, "Todo");
+    }
+
+    #[test]
+    fn test_struct_default_overridden() {
+        let code = r#"
+            struct Todo {
+                name: String = ""
+                done: Bool = false
+            }
+            Todo { done: true }
+        "#;
+
+        let (vm, idents) = run_vm(code);
+
+        assert_eq!(vm.actions, vec![
+            Action::Construct { id: 0, name: "Todo".to_string() },
+            Action::StoreField {
+                id: 0,
+                field: idents["name"],
+                val: Value::Str("".to_string()),
+            },
+            Action::StoreField {
+                id: 0,
+                field: idents["done"],
+                val: Value::Bool(true),
+            },
+            Action::Quit,
+        ]);
+    }
+
+    #[test]
+    fn test_assign_field() {
+        let (vm, idents) = run_vm(r#"
+            todo = Todo { done: false }
+            todo.done = true
+            todo.done
+        "#);
+
+        assert_eq!(vm.stack, vec![Value::Bool(true)]);
+        assert_eq!(vm.actions[2], Action::StoreField {
+            id: 0,
+            field: idents["done"],
+            val: Value::Bool(true),
+        });
+    }
+
+    #[test]
+    fn test_array_methods() {
+        let code = r#"
+            let todos = [Todo { name: "milk", done: true }, Todo { name: "eggs", done: false }]
+            let limit = 4
+            let nums = [1, 5, 10]
+            [
+                todos.map(todo => todo.name),
+                todos.filter(todo => todo.done).map(todo => todo.name),
+                nums.filter((n) => n > limit).map((n) => n * 2),
+            ]
+        "#;
+
+        let str = |s: &str| Value::Str(s.to_string());
+
+        assert_eq!(run_code(code), vec![
+            Value::Array(vec![
+                Value::Array(vec![str("milk"), str("eggs")]),
+                Value::Array(vec![str("milk")]),
+                Value::Array(vec![Value::Int(10), Value::Int(20)]),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn test_todo_example() {
+        let ast = Parser::new(include_str!("../examples/todo.do")).parse().unwrap();
+        assert!(TypeChecker::new().check(&ast).is_ok());
+        let res = Compiler::new().compile(ast).unwrap();
+
+        let mut vm = Vm::new();
+        vm.load(&res);
+        let actions = vm.work().unwrap();
+
+        assert!(actions.iter().any(|a| matches!(a, Action::Construct { name, .. } if name == "Window")));
+        assert_eq!(actions.last(), Some(&Action::Quit));
+    }
+
+    #[test]
+    fn test_component_tree_actions() {
+        let (vm, idents) = run_vm(r#"Div { children: [Text { text: "a" }] }"#);
+
+        assert_eq!(vm.actions, vec![
+            Action::Construct { id: 0, name: "Div".to_string() },
+            Action::Construct { id: 1, name: "Text".to_string() },
+            Action::StoreField {
+                id: 1,
+                field: idents["text"],
+                val: Value::Str("a".to_string()),
+            },
+            Action::StoreField {
+                id: 0,
+                field: idents["children"],
+                val: Value::Array(vec![Value::Object(ObjectRef(1))]),
+            },
+            Action::Quit,
+        ]);
+        assert_eq!(vm.stack, vec![Value::Object(ObjectRef(0))]);
+    }
 
     // #[test]
     // fn it_works() {